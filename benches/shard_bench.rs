@@ -0,0 +1,118 @@
+//! Throughput of `ShardedBMgr` by thread count, and its hit rate against
+//! one unsharded `BMgr` of the same size, on the zipf traces.
+//!
+//! cargo bench --bench shard_bench [-- trace_file ...]
+
+use std::time::Instant;
+use std::{env, fs, process};
+
+use ustc_adbs_lab_rust::buffer_manager::BMgr;
+use ustc_adbs_lab_rust::config::*;
+use ustc_adbs_lab_rust::data_storage_manager::DSMgr;
+use ustc_adbs_lab_rust::sharded_buffer_manager::ShardedBMgr;
+
+const BENCH_FILE_NAME: &str = "./target/shard_bench.dbf";
+const TRACES: [&str; 3] = [
+    "data/data-5w-1000-zipf.txt",
+    "data/data-5w-1w-zipf.txt",
+    "data/data-5w-50w-zipf.txt",
+];
+const THREAD_NUMS: [usize; 5] = [1, 2, 4, 8, 16];
+const SHARD_NUMS: [usize; 5] = [1, 4, 16, 64, 256];
+
+fn main() {
+    // cargo bench passes --bench to the binary
+    let args: Vec<String> = env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    let traces: Vec<&str> = match args.is_empty() {
+        true => TRACES.to_vec(),
+        false => args.iter().map(String::as_str).collect(),
+    };
+
+    let _ = fs::remove_file(BENCH_FILE_NAME);
+    DSMgr::new(BENCH_FILE_NAME).new_pages(50000);
+    for trace in traces {
+        let accesses = read_trace(trace).unwrap_or_else(|err| {
+            println!("cannot read {}: {}", trace, err);
+            process::exit(1);
+        });
+        println!("{} ({} accesses, {} frames)", trace, accesses.len(), FRAME_NUM);
+        hit_rate_loss(&accesses);
+        scaling(&accesses);
+        println!();
+    }
+    let _ = fs::remove_file(BENCH_FILE_NAME);
+}
+
+fn read_trace(path: &str) -> Result<Vec<(bool, PageId)>, Box<dyn std::error::Error>> {
+    let mut accesses = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let (is_dirty, page_id) = line.split_once(',').ok_or("malformed trace line")?;
+        accesses.push((is_dirty.parse::<u8>()? != 0, page_id.trim().parse::<PageId>()? - 1));
+    }
+    Ok(accesses)
+}
+
+/// Hit rate of a single-threaded replay for each shard count.
+fn hit_rate_loss(accesses: &[(bool, PageId)]) {
+    let mut buffer_manager = BMgr::new(BENCH_FILE_NAME, ReplacePolicy::Lru, FRAME_NUM);
+    for &(is_dirty, page_id) in accesses {
+        buffer_manager.fix_page(page_id, is_dirty);
+        buffer_manager.unfix_page(page_id);
+    }
+    let stats = buffer_manager.get_stats();
+    let base = stats.get_hit_rate() * 100.0;
+    drop(buffer_manager);
+    println!(
+        "  unsharded BMgr hit rate: {:.2}% (fix p50 hit {} ns, miss {} ns)",
+        base,
+        stats.hit_latency.quantile(0.5),
+        stats.miss_latency.quantile(0.5)
+    );
+
+    for shard_num in SHARD_NUMS {
+        let buffer_manager = sharded(shard_num);
+        for &(is_dirty, page_id) in accesses {
+            buffer_manager.with_page(page_id, is_dirty, |_| ()).unwrap();
+        }
+        let hit_rate = buffer_manager.get_stats().get_hit_rate() * 100.0;
+        println!(
+            "  {:>3} shards hit rate: {:.2}% ({:+.2} points)",
+            shard_num,
+            hit_rate,
+            hit_rate - base
+        );
+    }
+}
+
+/// Accesses per second with the trace dealt round-robin to the threads.
+fn scaling(accesses: &[(bool, PageId)]) {
+    print!("  threads");
+    for shard_num in SHARD_NUMS {
+        print!("  {:>10}", format!("{} shards", shard_num));
+    }
+    println!("  (k accesses/s)");
+    for thread_num in THREAD_NUMS {
+        print!("  {:>7}", thread_num);
+        for shard_num in SHARD_NUMS {
+            let buffer_manager = sharded(shard_num);
+            let start = Instant::now();
+            std::thread::scope(|s| {
+                for t in 0..thread_num {
+                    let buffer_manager = &buffer_manager;
+                    s.spawn(move || {
+                        for &(is_dirty, page_id) in accesses.iter().skip(t).step_by(thread_num) {
+                            buffer_manager.with_page(page_id, is_dirty, |_| ()).unwrap();
+                        }
+                    });
+                }
+            });
+            let throughput = accesses.len() as f64 / start.elapsed().as_secs_f64() / 1000.0;
+            print!("  {:>10.0}", throughput);
+        }
+        println!();
+    }
+}
+
+fn sharded(shard_num: usize) -> ShardedBMgr {
+    ShardedBMgr::new(DSMgr::new(BENCH_FILE_NAME), ReplacePolicy::Lru, FRAME_NUM, shard_num)
+}
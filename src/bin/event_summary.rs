@@ -0,0 +1,30 @@
+//! Summarize a buffer pool event log written by `EventRecorder`.
+//!
+//! cargo run --release --bin event_summary -- [event_log] [regret_window]
+
+use std::{env, process};
+
+use ustc_adbs_lab_rust::config::EVENT_LOG_FILE_NAME;
+use ustc_adbs_lab_rust::event_recorder::{read_events, summarize};
+
+const DEFAULT_REGRET_WINDOW: u64 = 10000;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let path = args.get(1).map_or(EVENT_LOG_FILE_NAME, String::as_str);
+    let regret_window = match args.get(2).map(|arg| arg.parse::<u64>()) {
+        None => DEFAULT_REGRET_WINDOW,
+        Some(Ok(regret_window)) => regret_window,
+        Some(Err(_)) => {
+            println!("usage: event_summary [event_log] [regret_window]");
+            process::exit(1);
+        }
+    };
+
+    let events = read_events(path).unwrap_or_else(|err| {
+        println!("cannot read {}: {}", path, err);
+        process::exit(1);
+    });
+    println!("{}: {} events", path, events.len());
+    print!("{}", summarize(&events, regret_window));
+}
@@ -0,0 +1,91 @@
+use crate::config::*;
+
+/// The kind of bulk operation a `BufferAccessStrategy` serves, which sets
+/// its default ring size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrategyType {
+    /// A large sequential read, e.g. a full table scan.
+    BulkRead,
+    /// A large load; a bigger ring leaves the log and the disk time to
+    /// catch up before a dirty ring page is written back.
+    BulkWrite,
+    /// A pass that reads and cleans up every page, e.g. vacuum.
+    Vacuum,
+}
+
+impl StrategyType {
+    pub fn get_default_ring_size(&self) -> usize {
+        match self {
+            StrategyType::BulkRead => 16,
+            StrategyType::BulkWrite => 64,
+            StrategyType::Vacuum => 16,
+        }
+    }
+}
+
+/// A small private ring of frames for a bulk operation, passed to
+/// `BMgr::fix_page_with_strategy`. Once the ring is full, each page the
+/// operation reads in replaces the oldest page of the ring, written back
+/// first if dirty, so a nightly batch job cycles through a few frames
+/// instead of evicting the online working set. A ring frame the operation
+/// cannot reuse, because it was pinned or evicted meanwhile, is replaced by
+/// a regular victim.
+pub struct BufferAccessStrategy {
+    strategy_type: StrategyType,
+    ring_size: usize,
+    // the pages read in through the ring and their frames
+    ring: Vec<(PageId, FrameId)>,
+    // the ring slot to recycle next, once the ring is full
+    current: usize,
+    num_reuses: usize,
+}
+
+impl BufferAccessStrategy {
+    pub fn new(strategy_type: StrategyType) -> BufferAccessStrategy {
+        Self::with_ring_size(strategy_type, strategy_type.get_default_ring_size())
+    }
+
+    pub fn with_ring_size(strategy_type: StrategyType, ring_size: usize) -> BufferAccessStrategy {
+        assert!(ring_size > 0);
+        BufferAccessStrategy {
+            strategy_type,
+            ring_size,
+            ring: Vec::with_capacity(ring_size),
+            current: 0,
+            num_reuses: 0,
+        }
+    }
+
+    pub fn get_strategy_type(&self) -> StrategyType {
+        self.strategy_type
+    }
+
+    pub fn get_ring_size(&self) -> usize {
+        self.ring_size
+    }
+
+    /// The ring entry to recycle for the next page, `None` while the ring
+    /// is still filling up.
+    pub(crate) fn next_victim(&self) -> Option<(PageId, FrameId)> {
+        (self.ring.len() == self.ring_size).then(|| self.ring[self.current])
+    }
+
+    /// Record the frame the next page was read into.
+    pub(crate) fn put(&mut self, page_id: PageId, frame_id: FrameId) {
+        if self.ring.len() < self.ring_size {
+            self.ring.push((page_id, frame_id));
+        } else {
+            self.ring[self.current] = (page_id, frame_id);
+            self.current = (self.current + 1) % self.ring_size;
+        }
+    }
+
+    pub(crate) fn record_reuse(&mut self) {
+        self.num_reuses += 1;
+    }
+
+    /// Pages read into a recycled ring frame.
+    pub fn get_reuse_num(&self) -> usize {
+        self.num_reuses
+    }
+}
@@ -1,25 +1,58 @@
 use std::collections::HashMap;
 use std::ops::Not;
+use std::sync::Arc;
+use std::time::Instant;
 
 use crate::config::*;
+use crate::buffer_access_strategy::BufferAccessStrategy;
+use crate::buffer_stats::BufferStats;
 use crate::data_storage_manager::DSMgr;
+use crate::encryption::PageCipher;
+use crate::event_recorder::{EventKind, EventRecorder};
+use crate::log_manager::{LogBody, LogManager, INVALID_LSN};
 use crate::page::*;
 use crate::replacer::*;
 
 pub struct BMgr {
-    data_storage_manager: DSMgr,
+    data_storage_manager: Arc<DSMgr>,
     // capacity: usize,
     free_list: Vec<usize>,
+    page_size: usize,
+    frames: AlignedBuf, // frame_num * page_size bytes, frame i at i * page_size
     pages: Vec<Page>,
-    replacer: Box<dyn Replacer>,
+    replacer: Box<dyn Replacer + Send>,
     page_table: HashMap<PageId, FrameId>,
-    num_write_io: usize,
-    num_read_io: usize,
-    num_hits: usize,
+    // write-ahead log forced before a dirty page is written, if any
+    log_manager: Option<Arc<LogManager>>,
+    steal_policy: StealPolicy,
+    force_policy: ForcePolicy,
+    // encrypts pages on their way to and from DSMgr, if set
+    page_cipher: Option<PageCipher>,
+    event_recorder: Option<EventRecorder>,
+    stats: BufferStats,
 }
 
 impl BMgr {
+    #[allow(dead_code)]
     pub fn new(db_file_name: &str, replace_policy: ReplacePolicy, frame_num: usize) -> BMgr {
+        Self::with_storage(DSMgr::new(db_file_name), replace_policy, frame_num)
+    }
+
+    pub fn with_storage(
+        data_storage_manager: DSMgr,
+        replace_policy: ReplacePolicy,
+        frame_num: usize,
+    ) -> BMgr {
+        Self::with_shared_storage(Arc::new(data_storage_manager), replace_policy, frame_num)
+    }
+
+    /// A pool over a `DSMgr` shared with other pools. Each page must only
+    /// ever be cached by one of them, see `BufferPoolSet`.
+    pub fn with_shared_storage(
+        data_storage_manager: Arc<DSMgr>,
+        replace_policy: ReplacePolicy,
+        frame_num: usize,
+    ) -> BMgr {
         let mut free_list = Vec::with_capacity(frame_num);
         let mut pages = Vec::with_capacity(frame_num);
         for i in (0..frame_num).rev() {
@@ -28,83 +61,329 @@ impl BMgr {
         for _ in 0..frame_num {
             pages.push(Page::new(None));
         }
+        let page_size = data_storage_manager.get_page_size();
+        let frames = AlignedBuf::new(frame_num * page_size);
 
-        let replacer: Box<dyn Replacer> = match replace_policy {
+        let replacer: Box<dyn Replacer + Send> = match replace_policy {
             ReplacePolicy::Lru => Box::new(LruReplacer::new(frame_num)),
             ReplacePolicy::Clock => Box::new(ClockReplacer::new(frame_num)),
         };
 
-        let data_storage_manager = DSMgr::new(db_file_name);
-
         BMgr {
             data_storage_manager,
             // capacity: frame_num,
             free_list,
+            page_size,
+            frames,
             pages,
             replacer,
             page_table: HashMap::new(),
-            num_write_io: 0,
-            num_read_io: 0,
-            num_hits: 0,
+            log_manager: None,
+            steal_policy: StealPolicy::Steal,
+            force_policy: ForcePolicy::NoForce,
+            page_cipher: None,
+            event_recorder: None,
+            stats: BufferStats::new(replace_policy),
+        }
+    }
+
+    /// Enforce the WAL rule: before a dirty page goes to disk, the log is
+    /// flushed up to the page's LSN.
+    pub fn set_log_manager(&mut self, log_manager: Arc<LogManager>) {
+        self.log_manager = Some(log_manager);
+    }
+
+    pub fn get_log_manager(&self) -> Option<&Arc<LogManager>> {
+        self.log_manager.as_ref()
+    }
+
+    /// Encrypt pages written to the database file and decrypt pages read
+    /// from it; frames stay in plain text. Set this before fixing any page.
+    pub fn set_page_cipher(&mut self, page_cipher: PageCipher) {
+        self.page_cipher = Some(page_cipher);
+    }
+
+    /// Log every hit, miss, eviction, flush, pin and unpin from now on.
+    pub fn set_event_recorder(&mut self, event_recorder: EventRecorder) {
+        self.event_recorder = Some(event_recorder);
+    }
+
+    fn record(&mut self, kind: EventKind, frame_id: FrameId) {
+        if let Some(event_recorder) = &mut self.event_recorder {
+            let page = &self.pages[frame_id];
+            let page_id = page.get_page_id().unwrap();
+            let result = event_recorder.record(kind, page_id, frame_id, page.is_dirty(), page.get_pin_count());
+            self.check_recording(result);
+        }
+    }
+
+    /// The event log is only a diagnostic, so the first error turns it off
+    /// instead of failing the pool.
+    fn check_recording(&mut self, result: Result<(), std::io::Error>) {
+        if let Err(err) = result {
+            eprintln!("event log disabled: {}", err);
+            self.event_recorder = None;
         }
     }
 
+    pub fn set_steal_policy(&mut self, steal_policy: StealPolicy) {
+        self.steal_policy = steal_policy;
+    }
+
+    pub fn get_steal_policy(&self) -> StealPolicy {
+        self.steal_policy
+    }
+
+    pub fn set_force_policy(&mut self, force_policy: ForcePolicy) {
+        self.force_policy = force_policy;
+    }
+
+    pub fn get_force_policy(&self) -> ForcePolicy {
+        self.force_policy
+    }
+
+    /// Overwrite `bytes.len()` bytes of a pinned frame at `offset`, logging
+    /// the before and after images for `txn_id`. Returns the new record's LSN,
+    /// which becomes the page LSN.
+    pub fn log_update(
+        &mut self,
+        txn_id: TxnId,
+        prev_lsn: Lsn,
+        frame_id: FrameId,
+        offset: usize,
+        bytes: &[u8],
+    ) -> Lsn {
+        let log_manager = self.log_manager.as_ref().expect("no log manager");
+        let page_id = self.pages[frame_id].get_page_id().unwrap();
+        let before = self.get_data_ref(frame_id)[offset..offset + bytes.len()].to_vec();
+        let lsn = log_manager.append(
+            prev_lsn,
+            txn_id,
+            LogBody::Update {
+                page_id,
+                offset,
+                before,
+                after: bytes.to_vec(),
+            },
+        );
+        self.apply_update(frame_id, offset, bytes, lsn);
+        self.pages[frame_id].add_txn(txn_id);
+        lsn
+    }
+
+    /// Called before `txn_id` commits: under `ForcePolicy::Force` the cached
+    /// pages it dirtied are written now, as one batch. Its pages become
+    /// evictable under `StealPolicy::NoSteal`.
+    pub fn commit_txn(&mut self, txn_id: TxnId) -> Result<(), std::io::Error> {
+        let frames: Vec<FrameId> = (0..self.pages.len())
+            .filter(|&frame_id| self.pages[frame_id].get_txns().contains(&txn_id))
+            .collect();
+        if self.force_policy == ForcePolicy::Force {
+            let dirty_frames: Vec<FrameId> = frames
+                .iter()
+                .copied()
+                .filter(|&frame_id| self.pages[frame_id].is_dirty())
+                .collect();
+            self.write_frames(&dirty_frames)?;
+        }
+        for frame_id in frames {
+            self.pages[frame_id].remove_txn(txn_id);
+        }
+        Ok(())
+    }
+
+    /// Called once `txn_id` has been rolled back.
+    pub fn abort_txn(&mut self, txn_id: TxnId) {
+        for page in &mut self.pages {
+            page.remove_txn(txn_id);
+        }
+    }
+
+    /// Apply an already logged change at `lsn` to a pinned frame, as redo and
+    /// undo do during recovery.
+    pub fn apply_update(&mut self, frame_id: FrameId, offset: usize, bytes: &[u8], lsn: Lsn) {
+        let data = self.get_data(frame_id);
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        set_page_lsn(data, lsn);
+        let page = &mut self.pages[frame_id];
+        page.set_dirty(true);
+        page.set_rec_lsn(lsn);
+    }
+
+    /// The dirty page table: each dirty page with the LSN of the first update
+    /// that dirtied it since it was last written.
+    pub fn get_dirty_page_table(&self) -> Vec<(PageId, Lsn)> {
+        self.pages
+            .iter()
+            .filter(|page| page.is_dirty())
+            .filter_map(|page| Some((page.get_page_id()?, page.get_rec_lsn()?)))
+            .collect()
+    }
+
     pub fn fix_page(&mut self, page_id: PageId, is_dirty: bool) -> Option<FrameId> {
+        self.fix_page_with_hint(page_id, is_dirty, AccessType::Normal)
+    }
+
+    /// Like `fix_page`, telling the replacer how the page is accessed. A
+    /// page read in by a sequential scan is the first to go once unfixed,
+    /// while a cached page the scan hits keeps the hint it was read with.
+    pub fn fix_page_with_hint(
+        &mut self,
+        page_id: PageId,
+        is_dirty: bool,
+        access_type: AccessType,
+    ) -> Option<FrameId> {
+        let start = Instant::now();
         if let Some(frame_id) = self.page_table.get(&page_id) {
-            self.num_hits += 1;
+            self.stats.hits += 1;
             let page = &mut self.pages[*frame_id];
             if page.get_pin_count() == 0 {
                 self.replacer.remove(*frame_id);
             }
+            if access_type != AccessType::SequentialScan {
+                page.set_access_type(access_type);
+            }
             page.increment_pin_count();
             if is_dirty {
                 page.set_dirty(true);
             }
-            Some(*frame_id)
+            let frame_id = *frame_id;
+            self.record(EventKind::Hit, frame_id);
+            self.record(EventKind::Pin, frame_id);
+            self.stats.hit_latency.record(start.elapsed().as_nanos() as u64);
+            Some(frame_id)
         } else {
-            if let Some(frame_id) = self.select_victim() {
-                let page = &mut self.pages[frame_id];
-                // TODO optimize
-                page.get_data()
-                    .copy_from_slice(&self.data_storage_manager.read_page(page_id).unwrap());
-                self.num_read_io += 1;
-                self.page_table.insert(page_id, frame_id);
-                page.increment_pin_count();
-                page.set_page_id(page_id);
-                if is_dirty {
-                    page.set_dirty(true);
-                }
-                Some(frame_id)
-            } else {
-                None
+            let frame_id = self.select_victim()?;
+            self.load_page(frame_id, page_id, is_dirty, access_type);
+            self.stats.miss_latency.record(start.elapsed().as_nanos() as u64);
+            Some(frame_id)
+        }
+    }
+
+    /// Like `fix_page`, for bulk operations: a page that is not cached is
+    /// read into a frame of the strategy's ring, recycling the oldest ring
+    /// frame once the ring is full instead of taking a victim from the
+    /// replacer. A cached page is fixed where it is.
+    pub fn fix_page_with_strategy(
+        &mut self,
+        page_id: PageId,
+        is_dirty: bool,
+        strategy: &mut BufferAccessStrategy,
+    ) -> Option<FrameId> {
+        if self.page_table.contains_key(&page_id) {
+            return self.fix_page_with_hint(page_id, is_dirty, AccessType::SequentialScan);
+        }
+        let frame_id = match strategy.next_victim() {
+            // the ring frame may have been evicted and reused, or be pinned
+            Some((ring_page_id, frame_id))
+                if self.page_table.get(&ring_page_id) == Some(&frame_id) && self.is_evictable(frame_id) =>
+            {
+                self.replacer.remove(frame_id);
+                self.evict_frame(frame_id);
+                strategy.record_reuse();
+                frame_id
             }
+            _ => self.select_victim()?,
+        };
+        strategy.put(page_id, frame_id);
+        self.load_page(frame_id, page_id, is_dirty, AccessType::SequentialScan);
+        Some(frame_id)
+    }
+
+    /// Read a page into a free frame and pin it.
+    fn load_page(&mut self, frame_id: FrameId, page_id: PageId, is_dirty: bool, access_type: AccessType) {
+        let page_size = self.page_size;
+        let data = &mut self.frames[frame_id * page_size..(frame_id + 1) * page_size];
+        self.data_storage_manager.read_page_into(page_id, data).unwrap();
+        if let Some(page_cipher) = &self.page_cipher {
+            page_cipher.decrypt(page_id, data);
+        }
+        self.stats.misses += 1;
+        self.stats.read_io += 1;
+        let page = &mut self.pages[frame_id];
+        self.page_table.insert(page_id, frame_id);
+        page.increment_pin_count();
+        page.set_page_id(page_id);
+        page.set_access_type(access_type);
+        if is_dirty {
+            page.set_dirty(true);
         }
+        self.record(EventKind::Miss, frame_id);
+        self.record(EventKind::Pin, frame_id);
     }
 
     #[allow(dead_code)]
     pub fn fix_new_page(&mut self, page_id: &mut PageId) -> Option<FrameId> {
+        self.fix_new_tablespace_page(0, page_id)
+    }
+
+    /// Like `fix_new_page`, allocating the page in tablespace `file_id`.
+    /// Returns None if `file_id` does not exist or has been dropped.
+    pub fn fix_new_tablespace_page(&mut self, file_id: FileId, page_id: &mut PageId) -> Option<FrameId> {
         if let Some(frame_id) = self.select_victim() {
-            *page_id = self.data_storage_manager.new_page();
+            match self.data_storage_manager.new_tablespace_pages(file_id, 1) {
+                Ok(new_page_id) => *page_id = new_page_id,
+                Err(_) => {
+                    self.free_list.push(frame_id);
+                    return None;
+                }
+            }
+            self.get_data(frame_id).fill(0);
             let page = &mut self.pages[frame_id];
             self.page_table.insert(*page_id, frame_id);
             page.increment_pin_count();
             page.set_page_id(*page_id);
+            page.set_access_type(AccessType::Normal);
             page.set_dirty(true);
+            self.stats.new_pages += 1;
+            self.record(EventKind::New, frame_id);
+            self.record(EventKind::Pin, frame_id);
             Some(frame_id)
         } else {
             None
         }
     }
 
+    pub fn create_tablespace(&self) -> Result<FileId, std::io::Error> {
+        self.data_storage_manager.create_tablespace()
+    }
+
+    /// Drop a tablespace, discarding its cached pages without writing them.
+    /// Fails if one of them is pinned.
+    pub fn drop_tablespace(&mut self, file_id: FileId) -> Result<(), std::io::Error> {
+        let frames: Vec<(PageId, FrameId)> = self
+            .page_table
+            .iter()
+            .filter(|(&page_id, _)| get_file_id(page_id) == file_id)
+            .map(|(&page_id, &frame_id)| (page_id, frame_id))
+            .collect();
+        if frames.iter().any(|&(_, frame_id)| self.pages[frame_id].get_pin_count() > 0) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ResourceBusy,
+                format!("tablespace {} has pinned pages", file_id),
+            ));
+        }
+        self.data_storage_manager.drop_tablespace(file_id)?;
+        for (page_id, frame_id) in frames {
+            self.page_table.remove(&page_id);
+            self.replacer.remove(frame_id);
+            self.pages[frame_id] = Page::new(None);
+            self.free_list.push(frame_id);
+        }
+        Ok(())
+    }
+
     pub fn unfix_page(&mut self, page_id: PageId) -> Option<FrameId> {
         if let Some(frame_id) = self.page_table.get(&page_id) {
             let page = &mut self.pages[*frame_id];
             assert!(page.get_pin_count() > 0);
             page.decrement_pin_count();
             if page.get_pin_count() == 0 {
-                self.replacer.insert(*frame_id);
+                self.replacer.insert_with_hint(*frame_id, page.get_access_type());
             }
-            Some(*frame_id)
+            let frame_id = *frame_id;
+            self.record(EventKind::Unpin, frame_id);
+            Some(frame_id)
         } else {
             None
         }
@@ -115,21 +394,232 @@ impl BMgr {
         self.free_list.len()
     }
 
+    /// Frames holding a page that is not pinned, i.e. candidates for eviction.
+    pub fn num_unpinned_frames(&self) -> usize {
+        self.replacer.size()
+    }
+
+    pub fn get_frame_num(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Grow or shrink the pool to `new_frame_num` frames. New frames go to
+    /// the free list. Shrinking evicts the pages in the frames at and above
+    /// `new_frame_num`, writing dirty ones first. A page there that cannot be
+    /// evicted, because it is pinned or, under `StealPolicy::NoSteal`,
+    /// dirtied by an active transaction, moves to a free or evictable frame
+    /// below `new_frame_num` instead, so look up its frame again by page id.
+    /// Fails without changing anything if there are more such pages than
+    /// `new_frame_num` frames.
+    pub fn resize(&mut self, new_frame_num: usize) -> Result<(), std::io::Error> {
+        let frame_num = self.pages.len();
+        if new_frame_num == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a buffer pool needs at least one frame",
+            ));
+        }
+        let in_use = (0..frame_num).filter(|&frame_id| !self.is_evictable(frame_id)).count();
+        if in_use > new_frame_num {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ResourceBusy,
+                format!("{} frames are in use", in_use),
+            ));
+        }
+
+        let tail_frames: Vec<FrameId> = (new_frame_num..frame_num)
+            .filter(|frame_id| !self.free_list.contains(frame_id))
+            .collect();
+        for &frame_id in &tail_frames {
+            if self.is_evictable(frame_id) {
+                self.replacer.remove(frame_id);
+                self.evict_frame(frame_id);
+            }
+        }
+        for &frame_id in &tail_frames {
+            if self.is_evictable(frame_id) {
+                continue;
+            }
+            let target = match self.free_list.iter().position(|&free| free < new_frame_num) {
+                Some(pos) => self.free_list.remove(pos),
+                None => {
+                    let (pages, steal_policy) = (&self.pages, self.steal_policy);
+                    let target = self
+                        .replacer
+                        .victim_if(&mut |victim| {
+                            victim < new_frame_num && Self::can_evict(&pages[victim], steal_policy)
+                        })
+                        .ok_or_else(|| {
+                            std::io::Error::new(
+                                std::io::ErrorKind::ResourceBusy,
+                                format!("no frame below {} to move frame {} to", new_frame_num, frame_id),
+                            )
+                        })?;
+                    self.evict_frame(target);
+                    target
+                }
+            };
+            self.move_frame(frame_id, target);
+        }
+        self.free_list.retain(|&frame_id| frame_id < new_frame_num);
+        // new frames are handed out before the old free ones, lowest first
+        self.free_list.extend((frame_num..new_frame_num).rev());
+        self.pages.resize_with(new_frame_num, || Page::new(None));
+        self.replacer.set_capacity(new_frame_num);
+
+        let page_size = self.page_size;
+        let mut frames = AlignedBuf::new(new_frame_num * page_size);
+        let len = frame_num.min(new_frame_num) * page_size;
+        frames[..len].copy_from_slice(&self.frames[..len]);
+        self.frames = frames;
+        Ok(())
+    }
+
     pub fn select_victim(&mut self) -> Option<FrameId> {
         if self.free_list.is_empty().not() {
             return self.free_list.pop();
         }
 
-        let victim_frame_id: FrameId = self.replacer.victim()?;
-        let page = &mut self.pages[victim_frame_id];
+        let victim = match self.steal_policy {
+            StealPolicy::Steal => self.replacer.victim(),
+            StealPolicy::NoSteal => {
+                // pass over pages with uncommitted updates, leaving them in place
+                let pages = &self.pages;
+                self.replacer
+                    .victim_if(&mut |frame_id| pages[frame_id].get_txns().is_empty())
+            }
+        };
+        self.stats.victim_scan_lengths.record(self.replacer.get_scan_len() as u64);
+        let Some(victim_frame_id) = victim else {
+            // every frame is pinned, the caller has to wait for an unfix
+            self.stats.pin_waits += 1;
+            return None;
+        };
+        self.evict_frame(victim_frame_id);
+        Some(victim_frame_id)
+    }
+
+    /// Move the page in frame `from` to the empty frame `to`.
+    fn move_frame(&mut self, from: FrameId, to: FrameId) {
+        let page_size = self.page_size;
+        self.frames.copy_within(from * page_size..(from + 1) * page_size, to * page_size);
+        self.pages.swap(from, to);
+        self.pages[from] = Page::new(None);
+        let page = &self.pages[to];
+        self.page_table.insert(page.get_page_id().unwrap(), to);
+        if page.get_pin_count() == 0 {
+            let access_type = page.get_access_type();
+            self.replacer.remove(from);
+            self.replacer.insert_with_hint(to, access_type);
+        }
+    }
+
+    /// Whether the page in a frame may be evicted now.
+    fn is_evictable(&self, frame_id: FrameId) -> bool {
+        Self::can_evict(&self.pages[frame_id], self.steal_policy)
+    }
+
+    fn can_evict(page: &Page, steal_policy: StealPolicy) -> bool {
+        page.get_pin_count() == 0 && Self::can_write(page, steal_policy)
+    }
+
+    /// Whether a page may be written to disk: under `StealPolicy::NoSteal`
+    /// not while it holds uncommitted updates.
+    fn can_write(page: &Page, steal_policy: StealPolicy) -> bool {
+        steal_policy == StealPolicy::Steal || page.get_txns().is_empty()
+    }
+
+    /// Write back the page in a frame that is out of the replacer if it is
+    /// dirty, and drop it from the page table.
+    fn evict_frame(&mut self, frame_id: FrameId) {
+        let page = &self.pages[frame_id];
         assert_eq!(page.get_pin_count(), 0);
-        if page.is_dirty() {
-            self.data_storage_manager.write_page(page).unwrap();
-            self.num_write_io += 1;
-            page.set_dirty(false);
+        let page_id = page.get_page_id().unwrap();
+        self.record(EventKind::Evict, frame_id);
+        self.stats.evictions += 1;
+        if self.pages[frame_id].is_dirty() {
+            self.stats.dirty_evictions += 1;
+            self.write_frame(frame_id);
+        } else {
+            self.stats.clean_evictions += 1;
         }
-        self.page_table.remove(&page.get_page_id().unwrap());
-        Some(victim_frame_id)
+        self.pages[frame_id].clear_txns();
+        self.page_table.remove(&page_id);
+    }
+
+    /// Write a dirty frame back, forcing the log up to its page LSN first.
+    fn write_frame(&mut self, frame_id: FrameId) {
+        let page_id = self.pages[frame_id].get_page_id().unwrap();
+        let page_size = self.page_size;
+        let data = &self.frames[frame_id * page_size..(frame_id + 1) * page_size];
+        if let Some(log_manager) = &self.log_manager {
+            log_manager.flush(get_page_lsn(data)).unwrap();
+        }
+        if let Some(page_cipher) = &mut self.page_cipher {
+            let mut encrypted = AlignedBuf::new(page_size);
+            encrypted.copy_from_slice(data);
+            page_cipher
+                .encrypt(&self.data_storage_manager, page_id, &mut encrypted)
+                .unwrap();
+            self.data_storage_manager.write_page(page_id, &encrypted).unwrap();
+        } else {
+            self.data_storage_manager.write_page(page_id, data).unwrap();
+        }
+        self.stats.write_io += 1;
+        self.record(EventKind::Flush, frame_id);
+        self.pages[frame_id].set_dirty(false);
+    }
+
+    /// Take a fuzzy checkpoint: log `active_txns` and the dirty page table
+    /// between begin and end checkpoint records, then point the log's master
+    /// record at it. Nothing else is flushed, except that the `max_flush`
+    /// dirty pages with the oldest recovery LSNs are written out first, so
+    /// regular checkpoints keep moving the redo start forward. Under
+    /// `StealPolicy::NoSteal` pages with uncommitted updates are skipped.
+    pub fn checkpoint(
+        &mut self,
+        active_txns: &[(TxnId, Lsn)],
+        max_flush: usize,
+    ) -> Result<Lsn, std::io::Error> {
+        let mut oldest: Vec<(Lsn, FrameId)> = self
+            .pages
+            .iter()
+            .enumerate()
+            .filter(|(_, page)| page.is_dirty() && Self::can_write(page, self.steal_policy))
+            .filter_map(|(frame_id, page)| Some((page.get_rec_lsn()?, frame_id)))
+            .collect();
+        oldest.sort_unstable();
+        let frames: Vec<FrameId> = oldest.iter().take(max_flush).map(|&(_, frame_id)| frame_id).collect();
+        self.write_frames(&frames)?;
+
+        let log_manager = self.log_manager.as_ref().expect("no log manager");
+        let begin_lsn = log_manager.append(INVALID_LSN, 0, LogBody::BeginCheckpoint);
+        let end_lsn = log_manager.append(
+            INVALID_LSN,
+            0,
+            LogBody::EndCheckpoint {
+                txns: active_txns.to_vec(),
+                dirty_pages: self.get_dirty_page_table(),
+            },
+        );
+        log_manager.flush(end_lsn)?;
+        log_manager.set_master_lsn(begin_lsn)?;
+        Ok(begin_lsn)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// The data of a frame; `frame_id` should be pinned by the caller.
+    #[allow(dead_code)]
+    pub fn get_data(&mut self, frame_id: FrameId) -> &mut Data {
+        &mut self.frames[frame_id * self.page_size..(frame_id + 1) * self.page_size]
+    }
+
+    pub fn get_data_ref(&self, frame_id: FrameId) -> &Data {
+        &self.frames[frame_id * self.page_size..(frame_id + 1) * self.page_size]
     }
 
     #[allow(dead_code)]
@@ -144,30 +634,94 @@ impl BMgr {
         page.set_dirty(false);
     }
 
+    /// Write every dirty page that may go to disk, see `can_write`.
     fn write_dirtys(&mut self) {
-        for page in &mut self.pages {
-            if page.is_dirty() {
-                self.data_storage_manager.write_page(page).unwrap();
-                self.num_write_io += 1;
-                page.set_dirty(false);
+        let dirty_frames: Vec<FrameId> = (0..self.pages.len())
+            .filter(|&frame_id| {
+                let page = &self.pages[frame_id];
+                page.is_dirty() && Self::can_write(page, self.steal_policy)
+            })
+            .collect();
+        self.write_frames(&dirty_frames).unwrap();
+        if let Some(event_recorder) = &mut self.event_recorder {
+            let result = event_recorder.flush();
+            self.check_recording(result);
+        }
+    }
+
+    /// Write dirty frames back as one batch with `DSMgr::write_pages`, after
+    /// one log flush up to the largest of their page LSNs.
+    fn write_frames(&mut self, frames: &[FrameId]) -> Result<(), std::io::Error> {
+        let page_size = self.page_size;
+        let dirty_frames: Vec<(PageId, FrameId)> = frames
+            .iter()
+            .map(|&frame_id| (self.pages[frame_id].get_page_id().unwrap(), frame_id))
+            .collect();
+        if let Some(log_manager) = &self.log_manager {
+            let page_lsns = dirty_frames
+                .iter()
+                .map(|&(_, frame_id)| get_page_lsn(self.get_data_ref(frame_id)));
+            if let Some(max_lsn) = page_lsns.max() {
+                log_manager.flush(max_lsn)?;
+            }
+        }
+        let mut encrypted = None;
+        if let (Some(page_cipher), false) = (&mut self.page_cipher, dirty_frames.is_empty()) {
+            let mut buffer = AlignedBuf::new(dirty_frames.len() * page_size);
+            for (&(page_id, frame_id), chunk) in dirty_frames.iter().zip(buffer.chunks_exact_mut(page_size)) {
+                chunk.copy_from_slice(&self.frames[frame_id * page_size..(frame_id + 1) * page_size]);
+                page_cipher.encrypt(&self.data_storage_manager, page_id, chunk)?;
             }
+            encrypted = Some(buffer);
         }
+        let dirty_pages: Vec<(PageId, &Data)> = match &encrypted {
+            Some(buffer) => dirty_frames
+                .iter()
+                .map(|&(page_id, _)| page_id)
+                .zip(buffer.chunks_exact(page_size))
+                .collect(),
+            None => dirty_frames
+                .iter()
+                .map(|&(page_id, frame_id)| (page_id, self.get_data_ref(frame_id)))
+                .collect(),
+        };
+        self.data_storage_manager.write_pages(&dirty_pages)?;
+        self.stats.write_io += dirty_frames.len() as u64;
+        for (_, frame_id) in dirty_frames {
+            self.record(EventKind::Flush, frame_id);
+            self.pages[frame_id].set_dirty(false);
+        }
+        Ok(())
+    }
+
+    /// A snapshot of the pool's counters and histograms since it was
+    /// created or last reset.
+    pub fn get_stats(&self) -> BufferStats {
+        self.stats.clone()
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = BufferStats::new(self.stats.replace_policy);
     }
 
     pub fn get_read_io_num(&self) -> usize {
-        self.num_read_io
+        self.stats.read_io as usize
     }
 
     pub fn get_write_io_num(&self) -> usize {
-        self.num_write_io
+        self.stats.write_io as usize
     }
 
     pub fn get_io_num(&self) -> usize {
-        self.num_read_io + self.num_write_io
+        (self.stats.read_io + self.stats.write_io) as usize
     }
 
     pub fn get_hit_num(&self) -> usize {
-        self.num_hits
+        self.stats.hits as usize
+    }
+
+    pub fn get_sync_num(&self) -> usize {
+        self.data_storage_manager.get_sync_num()
     }
 
     #[allow(dead_code)]
@@ -208,6 +762,7 @@ impl Drop for BMgr {
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_range_loop, clippy::useless_vec)]
 mod test {
     use super::*;
 
@@ -296,4 +851,575 @@ mod test {
 
         let _ = std::fs::remove_file(file);
     }
+
+    #[test]
+    fn test_buffer_manager_page_size() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let options = StorageOptions {
+            page_size: 8192,
+            ..Default::default()
+        };
+        {
+            let data_storage_manager = DSMgr::open(&file, options).unwrap();
+            let mut buffer_manager = BMgr::with_storage(data_storage_manager, ReplacePolicy::Clock, 3);
+            assert_eq!(buffer_manager.get_page_size(), 8192);
+            let mut page_id = 0;
+            for i in 0..6 {
+                let frame_id = buffer_manager.fix_new_page(&mut page_id).unwrap();
+                let data = buffer_manager.get_data(frame_id);
+                assert_eq!(data.len(), 8192);
+                // a new page does not carry the bytes of the page evicted for it
+                assert!(data.iter().all(|&byte| byte == 0));
+                data[8191] = i as u8;
+                buffer_manager.unfix_page(page_id);
+            }
+        }
+
+        let data_storage_manager = DSMgr::open(&file, options).unwrap();
+        let mut buffer_manager = BMgr::with_storage(data_storage_manager, ReplacePolicy::Lru, 2);
+        for i in 0..6 {
+            let frame_id = buffer_manager.fix_page(i, false).unwrap();
+            assert_eq!(buffer_manager.get_data_ref(frame_id)[8191], i as u8);
+            buffer_manager.unfix_page(i);
+        }
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_buffer_manager_wal() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let log_file = format!("./target/test_file_{:?}.log", std::thread::current().id());
+        let _ = std::fs::remove_file(&log_file);
+        let log_manager = Arc::new(LogManager::open(&log_file).unwrap());
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 2);
+        buffer_manager.set_log_manager(log_manager.clone());
+
+        let update_page = |buffer_manager: &mut BMgr, page_id: PageId| {
+            let frame_id = buffer_manager.fix_page(page_id, true).unwrap();
+            let lsn = log_manager.append(
+                INVALID_LSN,
+                1,
+                LogBody::Update {
+                    page_id,
+                    offset: PAGE_HEADER_SIZE,
+                    before: vec![0],
+                    after: vec![1],
+                },
+            );
+            let data = buffer_manager.get_data(frame_id);
+            data[PAGE_HEADER_SIZE] = 1;
+            set_page_lsn(data, lsn);
+            buffer_manager.unfix_page(page_id);
+            lsn
+        };
+
+        let mut page_id = 0;
+        for _ in 0..3 {
+            buffer_manager.fix_new_page(&mut page_id).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        // pages 1 and 2 are cached; updating them does not touch the log file
+        let lsn1 = update_page(&mut buffer_manager, 1);
+        let lsn2 = update_page(&mut buffer_manager, 2);
+        assert_eq!(log_manager.get_flush_num(), 0);
+
+        // evicting page 1 forces the log up to its LSN first
+        buffer_manager.fix_page(0, false).unwrap();
+        buffer_manager.unfix_page(0);
+        assert!(log_manager.get_flushed_lsn() > lsn1);
+        assert_eq!(log_manager.get_flush_num(), 1);
+
+        // flushing the pool forces the log up to the largest page LSN
+        let lsn0 = update_page(&mut buffer_manager, 0);
+        assert!(lsn0 > lsn2);
+        drop(buffer_manager);
+        assert!(log_manager.get_flushed_lsn() > lsn0);
+        assert_eq!(log_manager.get_flush_num(), 2);
+
+        let _ = std::fs::remove_file(file);
+        let _ = std::fs::remove_file(log_file);
+    }
+
+    #[test]
+    fn test_buffer_manager_txn_policy() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let log_file = format!("./target/test_file_{:?}.log", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let _ = std::fs::remove_file(&log_file);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 2);
+        buffer_manager.set_log_manager(Arc::new(LogManager::open(&log_file).unwrap()));
+        let mut page_id = 0;
+        for _ in 0..3 {
+            buffer_manager.fix_new_page(&mut page_id).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        let update = |buffer_manager: &mut BMgr, txn_id: TxnId, page_id: PageId| {
+            let frame_id = buffer_manager.fix_page(page_id, false).unwrap();
+            buffer_manager.log_update(txn_id, INVALID_LSN, frame_id, PAGE_HEADER_SIZE, &[1]);
+            buffer_manager.unfix_page(page_id);
+        };
+
+        // no-steal: frames with uncommitted updates cannot be evicted
+        buffer_manager.set_steal_policy(StealPolicy::NoSteal);
+        update(&mut buffer_manager, 1, 1);
+        update(&mut buffer_manager, 1, 2);
+        assert_eq!(buffer_manager.fix_page(0, false), None);
+        // no-force: committing writes nothing
+        let write_io = buffer_manager.get_write_io_num();
+        buffer_manager.commit_txn(1).unwrap();
+        assert_eq!(buffer_manager.get_write_io_num(), write_io);
+        assert!(buffer_manager.fix_page(0, false).is_some());
+        buffer_manager.unfix_page(0);
+        assert_eq!(buffer_manager.get_write_io_num(), write_io + 1);
+
+        // only pages of uncommitted transactions are protected
+        update(&mut buffer_manager, 2, 0);
+        for page_id in [1, 2, 1, 2] {
+            buffer_manager.fix_page(page_id, false).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        let hits = buffer_manager.get_hit_num();
+        buffer_manager.fix_page(0, false).unwrap();
+        buffer_manager.unfix_page(0);
+        assert_eq!(buffer_manager.get_hit_num(), hits + 1);
+        buffer_manager.abort_txn(2);
+
+        // force: committing writes the transaction's dirty pages
+        buffer_manager.set_steal_policy(StealPolicy::Steal);
+        buffer_manager.set_force_policy(ForcePolicy::Force);
+        let write_io = buffer_manager.get_write_io_num();
+        update(&mut buffer_manager, 3, 1);
+        update(&mut buffer_manager, 4, 0);
+        buffer_manager.commit_txn(3).unwrap();
+        assert_eq!(buffer_manager.get_write_io_num(), write_io + 1);
+        let dirty_pages: Vec<PageId> = buffer_manager
+            .get_dirty_page_table()
+            .into_iter()
+            .map(|(page_id, _)| page_id)
+            .collect();
+        assert_eq!(dirty_pages, vec![0]);
+
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+        let _ = std::fs::remove_file(log_file);
+    }
+
+    #[test]
+    fn test_buffer_manager_force_batch() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let log_file = format!("./target/test_file_{:?}.log", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let _ = std::fs::remove_file(&log_file);
+        let options = StorageOptions {
+            sync_policy: SyncPolicy::OnEveryWrite,
+            ..Default::default()
+        };
+        let data_storage_manager = DSMgr::open(&file, options).unwrap();
+        data_storage_manager.new_pages(4);
+        let mut buffer_manager = BMgr::with_storage(data_storage_manager, ReplacePolicy::Lru, 4);
+        buffer_manager.set_log_manager(Arc::new(LogManager::open(&log_file).unwrap()));
+        buffer_manager.set_force_policy(ForcePolicy::Force);
+        for page_id in 0..3 {
+            let frame_id = buffer_manager.fix_page(page_id, false).unwrap();
+            buffer_manager.log_update(1, INVALID_LSN, frame_id, PAGE_HEADER_SIZE, &[1]);
+            buffer_manager.unfix_page(page_id);
+        }
+
+        // the transaction's pages go out in one write with one sync
+        let (write_io, sync_num) = (buffer_manager.get_write_io_num(), buffer_manager.get_sync_num());
+        buffer_manager.commit_txn(1).unwrap();
+        assert_eq!(buffer_manager.get_write_io_num(), write_io + 3);
+        assert_eq!(buffer_manager.get_sync_num(), sync_num + 1);
+        assert!(buffer_manager.get_dirty_page_table().is_empty());
+
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+        let _ = std::fs::remove_file(log_file);
+    }
+
+    #[test]
+    fn test_buffer_manager_no_steal_flush() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let log_file = format!("./target/test_file_{:?}.log", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let _ = std::fs::remove_file(&log_file);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 4);
+        buffer_manager.set_log_manager(Arc::new(LogManager::open(&log_file).unwrap()));
+        buffer_manager.set_steal_policy(StealPolicy::NoSteal);
+        let mut page_id = 0;
+        for _ in 0..2 {
+            buffer_manager.fix_new_page(&mut page_id).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        buffer_manager.checkpoint(&[], usize::MAX).unwrap();
+        // page 0 is committed, page 1 is not
+        for (txn_id, page_id) in [(1, 0), (2, 1)] {
+            let frame_id = buffer_manager.fix_page(page_id, false).unwrap();
+            buffer_manager.log_update(txn_id, INVALID_LSN, frame_id, PAGE_HEADER_SIZE, &[1]);
+            buffer_manager.unfix_page(page_id);
+        }
+        buffer_manager.commit_txn(1).unwrap();
+
+        // neither a checkpoint nor the final flush writes page 1
+        let write_io = buffer_manager.get_write_io_num();
+        buffer_manager.checkpoint(&[(2, INVALID_LSN)], usize::MAX).unwrap();
+        assert_eq!(buffer_manager.get_write_io_num(), write_io + 1);
+        let dirty_pages: Vec<PageId> = buffer_manager
+            .get_dirty_page_table()
+            .into_iter()
+            .map(|(page_id, _)| page_id)
+            .collect();
+        assert_eq!(dirty_pages, vec![1]);
+        drop(buffer_manager);
+
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 4);
+        for (page_id, byte) in [(0, 1), (1, 0)] {
+            let frame_id = buffer_manager.fix_page(page_id, false).unwrap();
+            assert_eq!(buffer_manager.get_data_ref(frame_id)[PAGE_HEADER_SIZE], byte);
+            buffer_manager.unfix_page(page_id);
+        }
+
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+        let _ = std::fs::remove_file(log_file);
+    }
+
+    #[test]
+    fn test_buffer_manager_encryption() {
+        use crate::encryption::EnvKeyProvider;
+
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let key_var = "ADBS_TEST_PAGE_KEY";
+        std::env::set_var(key_var, "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let key_provider = EnvKeyProvider::new(key_var);
+
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 2);
+        buffer_manager.set_page_cipher(PageCipher::new(&key_provider).unwrap());
+        let mut page_id = 0;
+        for _ in 0..3 {
+            let frame_id = buffer_manager.fix_new_page(&mut page_id).unwrap();
+            buffer_manager.get_data(frame_id)[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 6].copy_from_slice(b"secret");
+            buffer_manager.unfix_page(page_id);
+        }
+        // page 0 was evicted and page 1 and 2 are written on drop
+        drop(buffer_manager);
+
+        // the file holds no plain text
+        let data_storage_manager = DSMgr::new(&file);
+        for page_id in 0..3 {
+            let data = data_storage_manager.read_page(page_id).unwrap();
+            assert!(!data.windows(6).any(|w| w == b"secret"));
+        }
+        drop(data_storage_manager);
+
+        // reopening with the same key reads the pages back
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 2);
+        buffer_manager.set_page_cipher(PageCipher::new(&key_provider).unwrap());
+        for page_id in 0..3 {
+            let frame_id = buffer_manager.fix_page(page_id, false).unwrap();
+            assert_eq!(&buffer_manager.get_data_ref(frame_id)[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 6], b"secret");
+            buffer_manager.unfix_page(page_id);
+        }
+
+        drop(buffer_manager);
+        std::env::remove_var(key_var);
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_buffer_manager_tablespaces() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 4);
+        let file_id = buffer_manager.create_tablespace().unwrap();
+
+        // one pool caches pages from both tablespaces
+        let mut page_ids = Vec::new();
+        for file_id in [0, file_id, file_id] {
+            let mut page_id = 0;
+            let frame_id = buffer_manager.fix_new_tablespace_page(file_id, &mut page_id).unwrap();
+            let text = format!("page {:#x}", page_id);
+            buffer_manager.get_data(frame_id)[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + text.len()]
+                .copy_from_slice(text.as_bytes());
+            buffer_manager.unfix_page(page_id);
+            page_ids.push(page_id);
+        }
+        assert_eq!(page_ids, vec![0, make_page_id(file_id, 0), make_page_id(file_id, 1)]);
+        for &page_id in &page_ids {
+            let frame_id = buffer_manager.fix_page(page_id, false).unwrap();
+            let text = format!("page {:#x}", page_id);
+            assert_eq!(
+                &buffer_manager.get_data_ref(frame_id)[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + text.len()],
+                text.as_bytes()
+            );
+            buffer_manager.unfix_page(page_id);
+        }
+        assert_eq!(buffer_manager.get_hit_num(), 3);
+
+        // dropping a tablespace frees its frames without writing them
+        buffer_manager.fix_page(page_ids[1], false).unwrap();
+        assert!(buffer_manager.drop_tablespace(file_id).is_err());
+        buffer_manager.unfix_page(page_ids[1]);
+        buffer_manager.drop_tablespace(file_id).unwrap();
+        assert_eq!(buffer_manager.num_free_frames(), 3);
+        assert_eq!(buffer_manager.get_write_io_num(), 0);
+
+        // a dropped or unknown tablespace gets no page and keeps the frame free
+        let mut page_id = 0;
+        assert!(buffer_manager.fix_new_tablespace_page(file_id, &mut page_id).is_none());
+        assert!(buffer_manager.fix_new_tablespace_page(file_id + 1, &mut page_id).is_none());
+        assert_eq!(buffer_manager.num_free_frames(), 3);
+
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_buffer_manager_resize() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Clock, 2);
+        let mut page_id = 0;
+        for i in 0..2 {
+            let frame_id = buffer_manager.fix_new_page(&mut page_id).unwrap();
+            buffer_manager.get_data(frame_id)[PAGE_HEADER_SIZE] = i + 1;
+            buffer_manager.unfix_page(page_id);
+        }
+
+        // growing keeps the cached pages and adds free frames
+        buffer_manager.resize(4).unwrap();
+        assert_eq!(buffer_manager.get_frame_num(), 4);
+        assert_eq!(buffer_manager.num_free_frames(), 2);
+        for i in 2..4 {
+            let frame_id = buffer_manager.fix_new_page(&mut page_id).unwrap();
+            assert_eq!(frame_id, i);
+            buffer_manager.get_data(frame_id)[PAGE_HEADER_SIZE] = i as u8 + 1;
+        }
+        assert_eq!(buffer_manager.get_write_io_num(), 0);
+        let frame_id = buffer_manager.fix_page(0, false).unwrap();
+        assert_eq!(buffer_manager.get_data_ref(frame_id)[PAGE_HEADER_SIZE], 1);
+
+        // pages 0, 2 and 3 are pinned, more than two frames hold
+        assert!(buffer_manager.resize(2).is_err());
+        assert_eq!(buffer_manager.get_frame_num(), 4);
+        buffer_manager.unfix_page(0);
+        // pinned pages 2 and 3 move down in place of pages 0 and 1
+        buffer_manager.resize(2).unwrap();
+        assert_eq!(buffer_manager.get_frame_num(), 2);
+        assert_eq!(buffer_manager.get_write_io_num(), 2);
+        assert_eq!(buffer_manager.num_unpinned_frames(), 0);
+        for i in 2..4 {
+            let frame_id = buffer_manager.unfix_page(i).unwrap();
+            assert!(frame_id < 2);
+            assert_eq!(buffer_manager.get_data_ref(frame_id)[PAGE_HEADER_SIZE], i as u8 + 1);
+        }
+        assert_eq!(buffer_manager.num_unpinned_frames(), 2);
+
+        // evicted pages were written and read back
+        for i in 0..4 {
+            let frame_id = buffer_manager.fix_page(i, false).unwrap();
+            assert!(frame_id < 2);
+            assert_eq!(buffer_manager.get_data_ref(frame_id)[PAGE_HEADER_SIZE], i as u8 + 1);
+            buffer_manager.unfix_page(i);
+        }
+        assert!(buffer_manager.resize(0).is_err());
+
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_buffer_manager_resize_steal() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let log_file = format!("./target/test_file_{:?}.log", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let _ = std::fs::remove_file(&log_file);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 3);
+        buffer_manager.set_log_manager(Arc::new(LogManager::open(&log_file).unwrap()));
+        let mut page_id = 0;
+        for _ in 0..3 {
+            buffer_manager.fix_new_page(&mut page_id).unwrap();
+        }
+        // pages 0 and 1 carry an active transaction's updates, page 2 stays pinned
+        for page_id in 0..2 {
+            let frame_id = buffer_manager.fix_page(page_id, false).unwrap();
+            buffer_manager.log_update(1, INVALID_LSN, frame_id, PAGE_HEADER_SIZE, &[page_id as u8 + 1]);
+            buffer_manager.unfix_page(page_id);
+            buffer_manager.unfix_page(page_id);
+        }
+
+        // under steal their frames can still take the pinned page
+        buffer_manager.resize(2).unwrap();
+        assert_eq!(buffer_manager.get_frame_num(), 2);
+        let frame_id = buffer_manager.unfix_page(2).unwrap();
+        assert!(frame_id < 2);
+        for page_id in 0..2 {
+            let frame_id = buffer_manager.fix_page(page_id, false).unwrap();
+            assert_eq!(buffer_manager.get_data_ref(frame_id)[PAGE_HEADER_SIZE], page_id as u8 + 1);
+            buffer_manager.unfix_page(page_id);
+        }
+
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+        let _ = std::fs::remove_file(log_file);
+    }
+
+    #[test]
+    fn test_buffer_manager_access_hint() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        DSMgr::new(&file).new_pages(100);
+        for replace_policy in [ReplacePolicy::Lru, ReplacePolicy::Clock] {
+            let mut buffer_manager = BMgr::new(&file, replace_policy, 8);
+            let hot_pages = 0..4;
+            for page_id in hot_pages.clone() {
+                buffer_manager.fix_page_with_hint(page_id, false, AccessType::Index).unwrap();
+                buffer_manager.unfix_page(page_id);
+            }
+            // a scan over every page, hot ones included, keeps the hot pages
+            for page_id in 0..100 {
+                buffer_manager.fix_page_with_hint(page_id, false, AccessType::SequentialScan).unwrap();
+                buffer_manager.unfix_page(page_id);
+            }
+            let read_io = buffer_manager.get_read_io_num();
+            for page_id in hot_pages.clone() {
+                buffer_manager.fix_page(page_id, false).unwrap();
+                buffer_manager.unfix_page(page_id);
+            }
+            assert_eq!(buffer_manager.get_read_io_num(), read_io);
+            assert_eq!(buffer_manager.get_hit_num(), 8);
+        }
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_buffer_manager_access_strategy() {
+        use crate::buffer_access_strategy::{BufferAccessStrategy, StrategyType};
+
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        DSMgr::new(&file).new_pages(108);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 16);
+        for page_id in 0..8 {
+            buffer_manager.fix_page(page_id, false).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+
+        // a bulk write over 100 pages cycles through a ring of 4 frames,
+        // writing each dirty page back as the ring wraps
+        let mut strategy = BufferAccessStrategy::with_ring_size(StrategyType::BulkWrite, 4);
+        for page_id in 0..108 {
+            let is_dirty = page_id >= 8;
+            buffer_manager.fix_page_with_strategy(page_id, is_dirty, &mut strategy).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        assert_eq!(strategy.get_reuse_num(), 96);
+        assert_eq!(buffer_manager.get_write_io_num(), 96);
+        assert_eq!(buffer_manager.num_free_frames(), 4);
+
+        // the working set survived
+        for page_id in 0..8 {
+            buffer_manager.fix_page(page_id, false).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        assert_eq!(buffer_manager.get_hit_num(), 16);
+        assert_eq!(buffer_manager.get_read_io_num(), 108);
+
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_buffer_manager_events() {
+        use crate::event_recorder::{read_events, summarize};
+
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let event_file = format!("./target/test_file_{:?}.events", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        DSMgr::new(&file).new_pages(3);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 2);
+        buffer_manager.set_event_recorder(EventRecorder::create(&event_file).unwrap());
+        for (page_id, is_dirty) in [(0, true), (1, false), (0, false), (2, false), (1, false)] {
+            buffer_manager.fix_page(page_id, is_dirty).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        drop(buffer_manager);
+
+        let events = read_events(&event_file).unwrap();
+        let kinds: Vec<(EventKind, PageId)> = events.iter().map(|e| (e.kind, e.page_id)).collect();
+        use EventKind::*;
+        assert_eq!(
+            kinds,
+            vec![
+                (Miss, 0), (Pin, 0), (Unpin, 0),
+                (Miss, 1), (Pin, 1), (Unpin, 1),
+                (Hit, 0), (Pin, 0), (Unpin, 0),
+                // page 1 is the LRU victim for page 2
+                (Evict, 1), (Miss, 2), (Pin, 2), (Unpin, 2),
+                // page 0 is evicted dirty and written back
+                (Evict, 0), (Flush, 0), (Miss, 1), (Pin, 1), (Unpin, 1),
+            ]
+        );
+        assert!(events[13].dirty && !events[9].dirty);
+        assert!(events.iter().enumerate().all(|(i, e)| e.seq == i as u64));
+        assert_eq!(summarize(&events, 10).regrets, 1);
+
+        // a failing event log is turned off, the pool keeps working
+        #[cfg(target_os = "linux")]
+        {
+            let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 2);
+            buffer_manager.set_event_recorder(EventRecorder::create("/dev/full").unwrap());
+            buffer_manager.fix_page(0, true).unwrap();
+            buffer_manager.unfix_page(0);
+            buffer_manager.write_dirtys();
+            assert!(buffer_manager.event_recorder.is_none());
+            buffer_manager.fix_page(1, false).unwrap();
+            buffer_manager.unfix_page(1);
+        }
+
+        let _ = std::fs::remove_file(file);
+        let _ = std::fs::remove_file(event_file);
+    }
+
+    #[test]
+    fn test_buffer_manager_stats() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        DSMgr::new(&file).new_pages(7);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Clock, 3);
+        for page_id in 0..3 {
+            buffer_manager.fix_page(page_id, true).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        buffer_manager.fix_page(0, false).unwrap();
+        buffer_manager.unfix_page(0);
+
+        // pin three new pages, then there is no frame left
+        let before = buffer_manager.get_stats();
+        for page_id in 3..6 {
+            buffer_manager.fix_page(page_id, false).unwrap();
+        }
+        assert_eq!(buffer_manager.fix_page(6, false), None);
+        let delta = buffer_manager.get_stats().delta(&before);
+        assert_eq!((delta.hits, delta.misses, delta.read_io), (0, 3, 3));
+        assert_eq!((delta.evictions, delta.dirty_evictions, delta.write_io), (3, 3, 3));
+        assert_eq!(delta.pin_waits, 1);
+        // the hand sweeps all frames once to clear their reference bits
+        assert_eq!(delta.victim_scan_lengths.count, 4);
+        assert_eq!(delta.victim_scan_lengths.sum, 4 + 1 + 1);
+        assert_eq!((delta.hit_latency.count, delta.miss_latency.count), (0, 3));
+        assert!(delta.to_json().contains("\"evictions\":3,\"dirty_evictions\":3,\"clean_evictions\":0,"));
+
+        let stats = buffer_manager.get_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 6));
+        assert_eq!(stats.hit_latency.count, 1);
+        buffer_manager.reset_stats();
+        assert_eq!(buffer_manager.get_stats(), BufferStats::new(ReplacePolicy::Clock));
+        assert_eq!(buffer_manager.get_io_num(), 0);
+
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
 }
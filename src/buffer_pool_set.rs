@@ -0,0 +1,193 @@
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::buffer_manager::BMgr;
+use crate::buffer_stats::BufferStats;
+use crate::config::*;
+use crate::data_storage_manager::DSMgr;
+
+/// Hits and I/O of one pool, see `BufferPoolSet::get_pool_stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolStats {
+    pub name: String,
+    pub frame_num: usize,
+    pub stats: BufferStats,
+}
+
+/// Several named buffer pools over one `DSMgr`, each with its own size and
+/// replacement policy, e.g. a small pool for index roots next to a large
+/// one for heap pages, so a scan cannot flush the index out.
+///
+/// `get_pool` routes a page to a pool: a caller hint naming a pool wins,
+/// then the first page id range added with `route_range` that holds the
+/// page, then the first pool. A hint naming no pool is an error, as is a
+/// set without pools. A page must always be routed to the same
+/// pool, or two pools end up caching diverging copies of it, so a caller
+/// that hints a page must hint it the same way every time.
+pub struct BufferPoolSet {
+    data_storage_manager: Arc<DSMgr>,
+    pools: Vec<(String, BMgr)>,
+    // page id range -> pool index
+    routes: Vec<(Range<PageId>, usize)>,
+}
+
+impl BufferPoolSet {
+    pub fn new(data_storage_manager: DSMgr) -> BufferPoolSet {
+        BufferPoolSet {
+            data_storage_manager: Arc::new(data_storage_manager),
+            pools: Vec::new(),
+            routes: Vec::new(),
+        }
+    }
+
+    /// Add a pool; the first one is the default for pages no rule routes.
+    pub fn add_pool(
+        &mut self,
+        name: &str,
+        replace_policy: ReplacePolicy,
+        frame_num: usize,
+    ) -> Result<(), std::io::Error> {
+        if self.find_pool(name).is_some() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("pool {} already exists", name),
+            ));
+        }
+        let pool = BMgr::with_shared_storage(self.data_storage_manager.clone(), replace_policy, frame_num);
+        self.pools.push((name.to_string(), pool));
+        Ok(())
+    }
+
+    /// Route the pages in `page_ids` to pool `name`. Earlier ranges win
+    /// where ranges overlap.
+    pub fn route_range(&mut self, page_ids: Range<PageId>, name: &str) -> Result<(), std::io::Error> {
+        let pool = self.find_pool(name).ok_or_else(|| Self::no_pool(name))?;
+        self.routes.push((page_ids, pool));
+        Ok(())
+    }
+
+    fn find_pool(&self, name: &str) -> Option<usize> {
+        self.pools.iter().position(|(pool_name, _)| pool_name == name)
+    }
+
+    fn no_pool(name: &str) -> Error {
+        Error::new(ErrorKind::NotFound, format!("no pool {}", name))
+    }
+
+    /// The name of the pool that caches `page_id`. Fails if `hint` names
+    /// no pool or there are no pools.
+    pub fn route(&self, page_id: PageId, hint: Option<&str>) -> Result<&str, std::io::Error> {
+        let pool = match hint {
+            Some(name) => self.find_pool(name).ok_or_else(|| Self::no_pool(name))?,
+            None => self
+                .routes
+                .iter()
+                .find(|(page_ids, _)| page_ids.contains(&page_id))
+                .map(|(_, pool)| *pool)
+                .unwrap_or(0),
+        };
+        match self.pools.get(pool) {
+            Some((name, _)) => Ok(name),
+            None => Err(Error::new(ErrorKind::NotFound, "no pools")),
+        }
+    }
+
+    /// The pool that caches `page_id`, to fix, access and unfix it in.
+    pub fn get_pool(&mut self, page_id: PageId, hint: Option<&str>) -> Result<&mut BMgr, std::io::Error> {
+        let name = self.route(page_id, hint)?.to_string();
+        Ok(self.get_pool_by_name(&name).unwrap())
+    }
+
+    pub fn get_pool_by_name(&mut self, name: &str) -> Option<&mut BMgr> {
+        let pool = self.find_pool(name)?;
+        Some(&mut self.pools[pool].1)
+    }
+
+    /// Move memory between pools without a restart, see `BMgr::resize`.
+    pub fn resize_pool(&mut self, name: &str, frame_num: usize) -> Result<(), std::io::Error> {
+        self.get_pool_by_name(name)
+            .ok_or_else(|| Self::no_pool(name))?
+            .resize(frame_num)
+    }
+
+    pub fn get_pool_stats(&self) -> Vec<PoolStats> {
+        self.pools
+            .iter()
+            .map(|(name, pool)| PoolStats {
+                name: name.clone(),
+                frame_num: pool.get_frame_num(),
+                stats: pool.get_stats(),
+            })
+            .collect()
+    }
+
+    pub fn print_pool_stats(&self) {
+        for pool_stats in self.get_pool_stats() {
+            let stats = &pool_stats.stats;
+            println!(
+                "pool {}: {} frames, hit number: {}, read io: {}, write io: {}",
+                pool_stats.name, pool_stats.frame_num, stats.hits, stats.read_io, stats.write_io
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::page::PAGE_HEADER_SIZE;
+
+    #[test]
+    fn buffer_pool_set_test() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let data_storage_manager = DSMgr::new(&file);
+        data_storage_manager.new_pages(100);
+        let mut pool_set = BufferPoolSet::new(data_storage_manager);
+        assert!(pool_set.route(0, None).is_err());
+        assert!(pool_set.get_pool(0, None).is_err());
+        pool_set.add_pool("heap", ReplacePolicy::Clock, 4).unwrap();
+        pool_set.add_pool("index", ReplacePolicy::Lru, 2).unwrap();
+        assert!(pool_set.add_pool("index", ReplacePolicy::Lru, 2).is_err());
+        pool_set.route_range(0..2, "index").unwrap();
+        assert!(pool_set.route_range(2..3, "log").is_err());
+
+        assert_eq!(pool_set.route(1, None).unwrap(), "index");
+        assert_eq!(pool_set.route(50, None).unwrap(), "heap");
+        assert_eq!(pool_set.route(50, Some("index")).unwrap(), "index");
+        assert!(pool_set.route(1, Some("unknown")).is_err());
+        assert!(pool_set.get_pool(1, Some("unknown")).is_err());
+
+        // a scan over the heap pages does not evict the index pages
+        for page_id in [0, 1] {
+            let pool = pool_set.get_pool(page_id, None).unwrap();
+            let frame_id = pool.fix_page(page_id, true).unwrap();
+            pool.get_data(frame_id)[PAGE_HEADER_SIZE] = 42;
+            pool.unfix_page(page_id);
+        }
+        for page_id in 2..100 {
+            let pool = pool_set.get_pool(page_id, None).unwrap();
+            pool.fix_page(page_id, false).unwrap();
+            pool.unfix_page(page_id);
+        }
+        for page_id in [0, 1] {
+            let pool = pool_set.get_pool(page_id, None).unwrap();
+            let frame_id = pool.fix_page(page_id, false).unwrap();
+            assert_eq!(pool.get_data_ref(frame_id)[PAGE_HEADER_SIZE], 42);
+            pool.unfix_page(page_id);
+        }
+
+        let stats = pool_set.get_pool_stats();
+        assert_eq!(stats[0].name, "heap");
+        assert_eq!((stats[0].stats.read_io, stats[0].stats.hits), (98, 0));
+        assert_eq!((stats[1].stats.read_io, stats[1].stats.hits), (2, 2));
+
+        pool_set.resize_pool("heap", 8).unwrap();
+        assert_eq!(pool_set.get_pool_stats()[0].frame_num, 8);
+        assert!(pool_set.resize_pool("log", 8).is_err());
+
+        drop(pool_set);
+        let _ = std::fs::remove_file(file);
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt::Write;
+
+use crate::config::*;
+
+const HISTOGRAM_BUCKETS: usize = 32;
+
+/// A histogram with power-of-two buckets: bucket `i` counts the values in
+/// `[2^i, 2^(i+1))`, with 0 in bucket 0 and everything from `2^31` in the
+/// last bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: u64,
+    pub buckets: [u64; HISTOGRAM_BUCKETS],
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        let bucket = (value.max(1).ilog2() as usize).min(HISTOGRAM_BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += value;
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.count.max(1) as f64
+    }
+
+    /// The lower bound of the bucket holding the `q` quantile, `0 <= q <= 1`.
+    pub fn quantile(&self, q: f64) -> u64 {
+        let rank = (q * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, num) in self.buckets.iter().enumerate() {
+            seen += num;
+            if seen >= rank {
+                return if i == 0 { 0 } else { 1 << i };
+            }
+        }
+        0
+    }
+
+    fn delta(&self, earlier: &Histogram) -> Histogram {
+        Histogram {
+            count: self.count.saturating_sub(earlier.count),
+            sum: self.sum.saturating_sub(earlier.sum),
+            buckets: std::array::from_fn(|i| self.buckets[i].saturating_sub(earlier.buckets[i])),
+        }
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        self.count += other.count;
+        self.sum += other.sum;
+        for (bucket, num) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += num;
+        }
+    }
+
+    fn write_json(&self, json: &mut String) {
+        let buckets: Vec<String> = self.buckets.iter().map(u64::to_string).collect();
+        write!(
+            json,
+            "{{\"count\":{},\"sum\":{},\"buckets\":[{}]}}",
+            self.count,
+            self.sum,
+            buckets.join(",")
+        )
+        .unwrap();
+    }
+}
+
+/// A snapshot of what a `BMgr` has done, from `BMgr::get_stats`. Take two
+/// snapshots and `delta` them for the counts of one interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferStats {
+    pub replace_policy: ReplacePolicy,
+    pub hits: u64,
+    pub misses: u64,
+    pub read_io: u64,
+    pub write_io: u64,
+    pub evictions: u64,
+    pub dirty_evictions: u64,
+    pub clean_evictions: u64,
+    pub new_pages: u64,
+    /// Fixes that failed because every frame was pinned.
+    pub pin_waits: u64,
+    /// Frames the replacer examined per victim, e.g. Clock hand moves.
+    pub victim_scan_lengths: Histogram,
+    /// Latency of `fix_page` in nanoseconds, on hits and on misses.
+    pub hit_latency: Histogram,
+    pub miss_latency: Histogram,
+}
+
+impl BufferStats {
+    pub fn new(replace_policy: ReplacePolicy) -> BufferStats {
+        BufferStats {
+            replace_policy,
+            hits: 0,
+            misses: 0,
+            read_io: 0,
+            write_io: 0,
+            evictions: 0,
+            dirty_evictions: 0,
+            clean_evictions: 0,
+            new_pages: 0,
+            pin_waits: 0,
+            victim_scan_lengths: Histogram::default(),
+            hit_latency: Histogram::default(),
+            miss_latency: Histogram::default(),
+        }
+    }
+
+    pub fn get_hit_rate(&self) -> f64 {
+        self.hits as f64 / (self.hits + self.misses).max(1) as f64
+    }
+
+    /// What happened since the `earlier` snapshot.
+    pub fn delta(&self, earlier: &BufferStats) -> BufferStats {
+        BufferStats {
+            replace_policy: self.replace_policy,
+            hits: self.hits.saturating_sub(earlier.hits),
+            misses: self.misses.saturating_sub(earlier.misses),
+            read_io: self.read_io.saturating_sub(earlier.read_io),
+            write_io: self.write_io.saturating_sub(earlier.write_io),
+            evictions: self.evictions.saturating_sub(earlier.evictions),
+            dirty_evictions: self.dirty_evictions.saturating_sub(earlier.dirty_evictions),
+            clean_evictions: self.clean_evictions.saturating_sub(earlier.clean_evictions),
+            new_pages: self.new_pages.saturating_sub(earlier.new_pages),
+            pin_waits: self.pin_waits.saturating_sub(earlier.pin_waits),
+            victim_scan_lengths: self.victim_scan_lengths.delta(&earlier.victim_scan_lengths),
+            hit_latency: self.hit_latency.delta(&earlier.hit_latency),
+            miss_latency: self.miss_latency.delta(&earlier.miss_latency),
+        }
+    }
+
+    /// Add the counts of another pool, e.g. another shard.
+    pub fn merge(&mut self, other: &BufferStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.read_io += other.read_io;
+        self.write_io += other.write_io;
+        self.evictions += other.evictions;
+        self.dirty_evictions += other.dirty_evictions;
+        self.clean_evictions += other.clean_evictions;
+        self.new_pages += other.new_pages;
+        self.pin_waits += other.pin_waits;
+        self.victim_scan_lengths.merge(&other.victim_scan_lengths);
+        self.hit_latency.merge(&other.hit_latency);
+        self.miss_latency.merge(&other.miss_latency);
+    }
+
+    pub fn to_json(&self) -> String {
+        let policy = match self.replace_policy {
+            ReplacePolicy::Lru => "lru",
+            ReplacePolicy::Clock => "clock",
+        };
+        let mut json = format!("{{\"replace_policy\":\"{}\"", policy);
+        let counters = [
+            ("hits", self.hits),
+            ("misses", self.misses),
+            ("read_io", self.read_io),
+            ("write_io", self.write_io),
+            ("evictions", self.evictions),
+            ("dirty_evictions", self.dirty_evictions),
+            ("clean_evictions", self.clean_evictions),
+            ("new_pages", self.new_pages),
+            ("pin_waits", self.pin_waits),
+        ];
+        for (name, value) in counters {
+            write!(json, ",\"{}\":{}", name, value).unwrap();
+        }
+        let histograms = [
+            ("victim_scan_lengths", &self.victim_scan_lengths),
+            ("hit_latency_ns", &self.hit_latency),
+            ("miss_latency_ns", &self.miss_latency),
+        ];
+        for (name, histogram) in histograms {
+            write!(json, ",\"{}\":", name).unwrap();
+            histogram.write_json(&mut json);
+        }
+        json.push('}');
+        json
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn buffer_stats_test() {
+        let mut histogram = Histogram::default();
+        for value in [0, 1, 3, 900, 1000, 1100] {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.buckets[0], 2);
+        assert_eq!(histogram.buckets[1], 1);
+        assert_eq!(histogram.buckets[9], 2);
+        assert_eq!(histogram.buckets[10], 1);
+        assert_eq!(histogram.quantile(0.5), 2);
+        assert_eq!(histogram.quantile(1.0), 1024);
+        assert_eq!(histogram.mean(), 3004.0 / 6.0);
+
+        let mut earlier = BufferStats::new(ReplacePolicy::Clock);
+        earlier.hits = 5;
+        earlier.hit_latency.record(100);
+        let mut stats = earlier.clone();
+        stats.hits = 8;
+        stats.misses = 2;
+        stats.hit_latency.record(200);
+        let delta = stats.delta(&earlier);
+        assert_eq!((delta.hits, delta.misses), (3, 2));
+        assert_eq!(delta.hit_latency.count, 1);
+        assert_eq!(delta.hit_latency.buckets[7], 1);
+        assert_eq!(delta.get_hit_rate(), 0.6);
+
+        let mut merged = delta.clone();
+        merged.merge(&delta);
+        assert_eq!((merged.hits, merged.hit_latency.count), (6, 2));
+
+        let json = delta.to_json();
+        assert!(json.starts_with("{\"replace_policy\":\"clock\",\"hits\":3,\"misses\":2,"));
+        assert!(json.contains(",\"hit_latency_ns\":{\"count\":1,\"sum\":200,\"buckets\":[0,0,0,0,0,0,0,1,0,"));
+        assert!(json.ends_with("]}}"));
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+    }
+}
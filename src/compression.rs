@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+use crate::config::*;
+use crate::page::*;
+
+/// Run-length encode `src` into `dst` (PackBits): a control byte `n < 128`
+/// is followed by `n + 1` literal bytes, `n >= 128` by one byte repeated
+/// `n - 126` times.
+pub fn compress(src: &[u8], dst: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < src.len() {
+        let run = src[i..].iter().take(129).take_while(|&&b| b == src[i]).count();
+        if run >= 2 {
+            dst.push((run + 126) as u8);
+            dst.push(src[i]);
+            i += run;
+            continue;
+        }
+        // literals up to the next run of at least 3 bytes
+        let start = i;
+        while i < src.len() && i - start < 128 {
+            if i + 2 < src.len() && src[i] == src[i + 1] && src[i] == src[i + 2] {
+                break;
+            }
+            i += 1;
+        }
+        dst.push((i - start - 1) as u8);
+        dst.extend_from_slice(&src[start..i]);
+    }
+}
+
+/// Decode `src` into `dst`; `None` unless it decodes to exactly `dst.len()` bytes.
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Option<()> {
+    let (mut i, mut j) = (0, 0);
+    while i < src.len() {
+        let n = src[i] as usize;
+        if n < 128 {
+            let literals = src.get(i + 1..i + 2 + n)?;
+            dst.get_mut(j..j + n + 1)?.copy_from_slice(literals);
+            i += n + 2;
+            j += n + 1;
+        } else {
+            let byte = *src.get(i + 1)?;
+            dst.get_mut(j..j + n - 126)?.fill(byte);
+            i += 2;
+            j += n - 126;
+        }
+    }
+    (j == dst.len()).then_some(())
+}
+
+// A slot holds one version of one page:
+// | magic: u32 | checksum: u32 | page_id: u64 | version: u64 | len: u32 |
+// | capacity: u32 | encoding: u8 | padding | payload |
+// The checksum covers everything after it up to the end of the payload.
+// A slot keeps its capacity for good, so a torn rewrite of a reused slot
+// cannot break the chain of slots.
+const SLOT_MAGIC: u32 = 0x43534441;
+const SLOT_HEADER_SIZE: usize = 40;
+const SLOT_ALIGN: usize = 128;
+const RAW: u8 = 0;
+const RLE: u8 = 1;
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    offset: u64,
+    capacity: usize,
+    len: usize,
+    encoding: u8,
+    version: u64,
+}
+
+/// Compressed pages stored in variable-size slots after the file header.
+/// The page-mapping table from `PageId` to slot lives in memory and is
+/// rebuilt on open from the slot headers; a page is only ever written to a
+/// free slot, and the newest intact version wins. Pages never written have
+/// no slot and read as zeros.
+pub struct CompressedPages {
+    page_size: usize,
+    slots: HashMap<PageId, Slot>,
+    // capacity -> offsets of free slots
+    free_slots: BTreeMap<usize, Vec<u64>>,
+    end: u64,
+    next_version: u64,
+}
+
+impl CompressedPages {
+    /// Scan the slots from `start` to the end of the file.
+    pub fn load(file: &File, start: u64, page_size: usize) -> Result<CompressedPages, std::io::Error> {
+        let mut pages = CompressedPages {
+            page_size,
+            slots: HashMap::new(),
+            free_slots: BTreeMap::new(),
+            end: start,
+            next_version: 1,
+        };
+        let file_len = file.metadata()?.len();
+        let mut buf = vec![0; SLOT_HEADER_SIZE + page_size];
+        while pages.end + SLOT_HEADER_SIZE as u64 <= file_len {
+            let offset = pages.end;
+            file.read_exact_at(&mut buf[..SLOT_HEADER_SIZE], offset)?;
+            let capacity = read_u32(&buf, 28) as usize;
+            if read_u32(&buf, 0) != SLOT_MAGIC
+                || capacity == 0
+                || !capacity.is_multiple_of(SLOT_ALIGN)
+                || offset + capacity as u64 > file_len
+            {
+                // a torn slot at the end of the file
+                break;
+            }
+            pages.end += capacity as u64;
+
+            let page_id = read_u64(&buf, 8) as PageId;
+            let slot = Slot {
+                offset,
+                capacity,
+                len: read_u32(&buf, 24) as usize,
+                encoding: buf[32],
+                version: read_u64(&buf, 16),
+            };
+            let intact = slot.len <= page_size
+                && SLOT_HEADER_SIZE + slot.len <= capacity
+                && {
+                    let end = SLOT_HEADER_SIZE + slot.len;
+                    file.read_exact_at(&mut buf[SLOT_HEADER_SIZE..end], offset + SLOT_HEADER_SIZE as u64)?;
+                    read_u32(&buf, 4) == compute_checksum(0, &buf[8..end])
+                };
+            if !intact {
+                pages.free(offset, capacity);
+                continue;
+            }
+            pages.next_version = pages.next_version.max(slot.version + 1);
+            match pages.slots.get(&page_id) {
+                Some(old) if old.version > slot.version => pages.free(offset, capacity),
+                _ => {
+                    if let Some(old) = pages.slots.insert(page_id, slot) {
+                        pages.free(old.offset, old.capacity);
+                    }
+                }
+            }
+        }
+        Ok(pages)
+    }
+
+    fn free(&mut self, offset: u64, capacity: usize) {
+        self.free_slots.entry(capacity).or_default().push(offset);
+    }
+
+    /// Take the smallest free slot of at least `capacity` bytes, or grow the file.
+    fn allocate(&mut self, capacity: usize) -> (u64, usize) {
+        let found = self
+            .free_slots
+            .range_mut(capacity..)
+            .next()
+            .map(|(&size, offsets)| (size, offsets.pop().unwrap(), offsets.is_empty()));
+        if let Some((size, offset, now_empty)) = found {
+            if now_empty {
+                self.free_slots.remove(&size);
+            }
+            return (offset, size);
+        }
+        let offset = self.end;
+        self.end += capacity as u64;
+        (offset, capacity)
+    }
+
+    pub fn read(&self, file: &File, page_id: PageId, buffer: &mut Data) -> Result<(), std::io::Error> {
+        let Some(slot) = self.slots.get(&page_id) else {
+            buffer.fill(0);
+            return Ok(());
+        };
+        let offset = slot.offset + SLOT_HEADER_SIZE as u64;
+        if slot.encoding == RAW {
+            return file.read_exact_at(buffer, offset);
+        }
+        let mut payload = vec![0; slot.len];
+        file.read_exact_at(&mut payload, offset)?;
+        decompress(&payload, buffer).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt compressed page")
+        })
+    }
+
+    pub fn write(&mut self, file: &File, page_id: PageId, data: &Data) -> Result<(), std::io::Error> {
+        let mut buf = vec![0; SLOT_HEADER_SIZE];
+        compress(data, &mut buf);
+        let mut encoding = RLE;
+        if buf.len() - SLOT_HEADER_SIZE >= self.page_size {
+            buf.truncate(SLOT_HEADER_SIZE);
+            buf.extend_from_slice(data);
+            encoding = RAW;
+        }
+        let len = buf.len() - SLOT_HEADER_SIZE;
+        let (offset, capacity) = self.allocate(buf.len().next_multiple_of(SLOT_ALIGN));
+        let version = self.next_version;
+        self.next_version += 1;
+
+        write_u32(&mut buf, 0, SLOT_MAGIC);
+        write_u64(&mut buf, 8, page_id as u64);
+        write_u64(&mut buf, 16, version);
+        write_u32(&mut buf, 24, len as u32);
+        write_u32(&mut buf, 28, capacity as u32);
+        buf[32] = encoding;
+        let checksum = compute_checksum(0, &buf[8..]);
+        write_u32(&mut buf, 4, checksum);
+        file.write_all_at(&buf, offset)?;
+        if offset + capacity as u64 == self.end {
+            // keep the file length at the end of the last slot
+            file.set_len(self.end)?;
+        }
+
+        let slot = Slot {
+            offset,
+            capacity,
+            len,
+            encoding,
+            version,
+        };
+        if let Some(old) = self.slots.insert(page_id, slot) {
+            self.free(old.offset, old.capacity);
+        }
+        Ok(())
+    }
+
+    /// Bytes taken by the slots of live pages.
+    pub fn get_stored_size(&self) -> u64 {
+        self.slots.values().map(|slot| slot.capacity as u64).sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compression_test() {
+        let mut page = vec![0u8; PAGE_SIZE];
+        page[100..110].copy_from_slice(b"abcdefghij");
+        page[2000..2300].fill(7);
+        for (i, b) in page[3000..3200].iter_mut().enumerate() {
+            *b = (i * 31 % 251) as u8;
+        }
+        let mut compressed = Vec::new();
+        compress(&page, &mut compressed);
+        assert!(compressed.len() < 400);
+        let mut decompressed = vec![0; PAGE_SIZE];
+        decompress(&compressed, &mut decompressed).unwrap();
+        assert_eq!(decompressed, page);
+
+        // an all-zero page compresses to 32 runs
+        compressed.clear();
+        compress(&vec![0; PAGE_SIZE], &mut compressed);
+        assert_eq!(compressed.len(), 64);
+        // output of the wrong length is rejected
+        assert_eq!(decompress(&compressed, &mut decompressed[..100]), None);
+        assert_eq!(decompress(&compressed[..10], &mut decompressed), None);
+    }
+}
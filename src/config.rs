@@ -1,36 +1,182 @@
 pub const FRAME_NUM: usize = 1024;
 pub const PAGE_SIZE: usize = 4096;
+pub const PAGE_ALIGN: usize = 4096;
 pub const DB_FILE_NAME : &str = "./target/data.dbf";
+pub const SYNC_POLICY: SyncPolicy = SyncPolicy::OnFlushAll;
+pub const DIRECT_IO: bool = false;
+pub const DOUBLE_WRITE_PAGES: usize = 0;
+pub const COMPRESSION: bool = false;
+pub const SEGMENT_PAGES: usize = 0;
+pub const LOG_FILE_NAME: &str = "./target/data.log";
+/// Record buffer pool events to `EVENT_LOG_FILE_NAME`, see `event_summary`.
+pub const EVENT_LOG: bool = false;
+pub const EVENT_LOG_FILE_NAME: &str = "./target/data.events";
+/// Trace accesses per transaction when a trace runs in transactions.
+pub const TXN_SIZE: usize = 10;
 
 pub type FrameId = usize;
 pub type PageId = usize;
-pub type Data = [u8; PAGE_SIZE];
+pub type FileId = usize;
+pub type Lsn = u64;
+pub type TxnId = u64;
+pub type Data = [u8];
 
+/// A `PageId` holds the file (tablespace) id in its top 16 bits and the page
+/// number within the file below them, so the pages of file 0 keep their
+/// plain numbers.
+pub const FILE_ID_SHIFT: u32 = 48;
+pub const MAX_FILE_ID: FileId = (1 << (usize::BITS - FILE_ID_SHIFT)) - 1;
+
+pub fn make_page_id(file_id: FileId, page_no: usize) -> PageId {
+    (file_id << FILE_ID_SHIFT) | page_no
+}
+
+pub fn get_file_id(page_id: PageId) -> FileId {
+    page_id >> FILE_ID_SHIFT
+}
+
+pub fn get_page_no(page_id: PageId) -> usize {
+    page_id & ((1 << FILE_ID_SHIFT) - 1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ReplacePolicy {
     Lru,
     Clock,
 }
 
+/// How a page is being accessed, passed to `BMgr::fix_page_with_hint` so
+/// the replacer can keep a large scan from flushing the hot pages out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessType {
+    #[default]
+    Normal,
+    /// One pass over many pages, each unlikely to be used again soon.
+    SequentialScan,
+    /// An index node on the way to a record.
+    Index,
+    /// A single record fetched by key.
+    Lookup,
+}
+
+/// Whether `BMgr::select_victim` may evict a page dirtied by a transaction
+/// that has not committed yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StealPolicy {
+    Steal,
+    NoSteal,
+}
+
+/// Whether committing a transaction writes the pages it dirtied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForcePolicy {
+    Force,
+    NoForce,
+}
+
+/// When `DSMgr` forces written pages to stable storage.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Only sync on an explicit `DSMgr::sync`.
+    Never,
+    /// Sync after every page write and after every batch write.
+    OnEveryWrite,
+    /// Sync once after every batch write, e.g. `BMgr::write_dirtys`.
+    OnFlushAll,
+    /// Sync after a write once the interval has passed since the last sync,
+    /// and after every batch write.
+    Periodic(std::time::Duration),
+}
+
+/// Options for opening the database file in `DSMgr`.
+#[derive(Debug, Clone, Copy)]
+pub struct StorageOptions {
+    pub sync_policy: SyncPolicy,
+    /// Open the file with `O_DIRECT` (Linux only), bypassing the OS page cache.
+    pub direct_io: bool,
+    /// A power of two, at least `PAGE_ALIGN`. Stored in the file header and
+    /// checked when an existing file is opened.
+    pub page_size: usize,
+    /// Size of the double-write area in pages, 0 to disable it. Stored in
+    /// the file header and checked when an existing file is opened.
+    pub double_write_pages: usize,
+    /// Store pages run-length compressed in variable-size slots. Cannot be
+    /// combined with direct I/O, a double-write area or segment files.
+    pub compression: bool,
+    /// Pages per segment file, 0 to keep each tablespace in one file. Once
+    /// a segment is full, pages spill into the next segment file. Stored in
+    /// the file header and checked when an existing file is opened.
+    pub segment_pages: usize,
+}
+
+impl Default for StorageOptions {
+    fn default() -> Self {
+        StorageOptions {
+            sync_policy: SyncPolicy::OnFlushAll,
+            direct_io: false,
+            page_size: PAGE_SIZE,
+            double_write_pages: 0,
+            compression: false,
+            segment_pages: 0,
+        }
+    }
+}
+
 pub struct Config {
     pub policy: ReplacePolicy,
     pub file_path: String,
+    /// Run the trace in logged transactions under these policies.
+    pub txn_policy: Option<(StealPolicy, ForcePolicy)>,
+    /// Page size of the database file, `PAGE_SIZE` unless `--page-size` is given.
+    pub page_size: usize,
 }
 
 impl Config {
     pub fn build(args: &[String]) -> Result<Config, &'static str> {
-        if args.len() != 3 {
-            return Err("Usage: cargo run --release -- [lru|clock] [file_path]");
+        const USAGE: &str = "Usage: cargo run --release -- [lru|clock] [file_path] \
+            [[steal|no-steal] [force|no-force]] [--page-size bytes]";
+        let mut args = args.to_vec();
+        let mut page_size = PAGE_SIZE;
+        if let Some(pos) = args.iter().position(|arg| arg == "--page-size") {
+            // checked against the file header when the database is opened
+            page_size = args.get(pos + 1).and_then(|size| size.parse().ok()).ok_or(USAGE)?;
+            args.drain(pos..pos + 2);
+        }
+        if args.len() != 3 && args.len() != 5 {
+            return Err(USAGE);
         }
 
         let policy = match args[1].as_str() {
             "lru" => ReplacePolicy::Lru,
             "clock" => ReplacePolicy::Clock,
             _ => {
-                return Err("Usage: cargo run --release -- [lru|clock] [file_path]");
+                return Err(USAGE);
             }
         };
         let file_path = args[2].clone();
 
-        Ok(Config { policy, file_path })
+        let txn_policy = if args.len() == 5 {
+            let steal = match args[3].as_str() {
+                "steal" => StealPolicy::Steal,
+                "no-steal" => StealPolicy::NoSteal,
+                _ => return Err(USAGE),
+            };
+            let force = match args[4].as_str() {
+                "force" => ForcePolicy::Force,
+                "no-force" => ForcePolicy::NoForce,
+                _ => return Err(USAGE),
+            };
+            Some((steal, force))
+        } else {
+            None
+        };
+
+        Ok(Config {
+            policy,
+            file_path,
+            txn_policy,
+            page_size,
+        })
     }
 }
\ No newline at end of file
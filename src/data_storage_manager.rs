@@ -1,34 +1,439 @@
+use crate::compression::CompressedPages;
 use crate::{config::*, page::*};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, IoSlice};
+use std::path::Path;
 use std::{
     fs::{File, OpenOptions},
-    io::{Read, Seek, Write}
+    os::unix::{
+        fs::{FileExt, OpenOptionsExt},
+        io::AsRawFd,
+    },
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Instant,
 };
 
+const MAGIC: &[u8; 8] = b"ADBSDBF\0";
+const FORMAT_VERSION: u32 = 1;
+
+/// The first `page_size` bytes of the file hold the header: magic (8 bytes),
+/// format version (u32), page size (u32), double-write pages (u32),
+/// compression flag (u32), for compressed files the number of pages (u64),
+/// pages per segment (u64), and the next free page cipher version (u64),
+/// little endian. A compressed file stores its
+/// pages in variable-size slots after the header, see `CompressedPages`.
+/// Otherwise, with a double-write area of `n` pages, the next block is its
+/// directory and the `n` blocks after it hold page copies. Page `page_id` is
+/// stored in the block after that, at `(page_id + 1 + n + 1) * page_size`,
+/// or at `(page_id + 1) * page_size` without a double-write area.
+///
+/// This file is tablespace 0. `create_tablespace` adds tablespaces in files
+/// `<path>.ts<file_id>`, each with its own header and no double-write area,
+/// and a `PageId` names a page by file id and page number, see
+/// `make_page_id`. With `segment_pages` set, a tablespace keeps that many
+/// pages in its file and spills the rest into headerless segment files
+/// `<file>.1`, `<file>.2`, ...
 pub struct DSMgr {
-    curr_file: File,
-    num_pages: usize,
+    curr_file: Arc<File>,
+    path: String,
+    page_size: usize,
+    direct_io: bool,
+    segment_pages: usize,
+    tablespaces: RwLock<HashMap<FileId, Arc<Tablespace>>>,
+    next_file_id: AtomicUsize,
+    sync_policy: SyncPolicy,
+    last_sync: Mutex<Instant>,
+    num_syncs: AtomicUsize,
+    double_write_pages: usize,
+    // held while a batch goes through the double-write area
+    double_write_lock: Mutex<()>,
+    num_repairs: usize,
+    compressed_pages: Option<Mutex<CompressedPages>>,
+    // the next page cipher version not reserved yet, as in the header
+    version_mark: Mutex<u64>,
+}
+
+struct Tablespace {
+    path: String,
+    // segment files, the first one being the tablespace file itself
+    segments: RwLock<Vec<Arc<File>>>,
+    num_pages: AtomicUsize,
+    // blocks before the first page in the tablespace file
+    first_data_block: usize,
+    // held while pages are added, see `new_tablespace_pages`
+    extend_lock: Mutex<()>,
 }
 
+// The double-write directory: | checksum: u32 | num_pages: u32 | page_id: u64 ... |
+// where the checksum covers everything after it.
+const DOUBLE_WRITE_DIR_BLOCK: usize = 1;
+const NUM_PAGES_OFFSET: u64 = 24;
+const VERSION_MARK_OFFSET: usize = 40;
+// the most buffers one pwritev call takes on Linux
+const IOV_MAX: usize = 1024;
+
 impl DSMgr {
+    #[allow(dead_code)]
     pub fn new(path: &str) -> DSMgr {
-        let curr_file = Self::open_file(&path);
-        let file_len = curr_file.metadata().unwrap().len() as usize;
-        let num_pages = file_len / PAGE_SIZE;
+        Self::with_sync_policy(path, SyncPolicy::OnFlushAll)
+    }
+
+    pub fn with_sync_policy(path: &str, sync_policy: SyncPolicy) -> DSMgr {
+        let options = StorageOptions {
+            sync_policy,
+            ..Default::default()
+        };
+        Self::open(path, options).unwrap()
+    }
+
+    pub fn open(path: &str, options: StorageOptions) -> Result<DSMgr, std::io::Error> {
+        let page_size = options.page_size;
+        if !page_size.is_power_of_two() || page_size < PAGE_ALIGN || page_size > u32::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid page size {}", page_size),
+            ));
+        }
+
+        let double_write_pages = options.double_write_pages;
+        if double_write_pages > (page_size - 8) / 8 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("double-write area of {} pages is too large", double_write_pages),
+            ));
+        }
+        if options.compression && (options.direct_io || double_write_pages > 0 || options.segment_pages > 0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "compression cannot be combined with direct I/O, double-write or segment files",
+            ));
+        }
+
+        let curr_file = Arc::new(Self::open_file(path, options.direct_io)?);
+        let file_len = curr_file.metadata()?.len() as usize;
+        let mut data_storage_manager = DSMgr {
+            curr_file: curr_file.clone(),
+            path: path.to_string(),
+            page_size,
+            direct_io: options.direct_io,
+            segment_pages: options.segment_pages,
+            tablespaces: RwLock::new(HashMap::new()),
+            next_file_id: AtomicUsize::new(1),
+            sync_policy: options.sync_policy,
+            last_sync: Mutex::new(Instant::now()),
+            num_syncs: AtomicUsize::new(0),
+            double_write_pages,
+            double_write_lock: Mutex::new(()),
+            num_repairs: 0,
+            compressed_pages: None,
+            version_mark: Mutex::new(1),
+        };
+        let first_data_block = match double_write_pages {
+            0 => 1,
+            n => n + 2,
+        };
+        if file_len == 0 {
+            Self::write_header(&curr_file, &options)?;
+            // reserve the double-write area, with an empty directory
+            curr_file.set_len((first_data_block * page_size) as u64)?;
+            curr_file.sync_all()?;
+        } else {
+            Self::check_header(&curr_file, &options)?;
+            let mut header = AlignedBuf::new(PAGE_ALIGN);
+            curr_file.read_exact_at(&mut header, 0)?;
+            // version 0 marks a page that was never encrypted
+            *data_storage_manager.version_mark.get_mut().unwrap() = read_u64(&header, VERSION_MARK_OFFSET).max(1);
+        }
+
+        let tablespace = data_storage_manager.load_tablespace(path, curr_file, first_data_block)?;
+        if let Some(file) = options.compression.then_some(&data_storage_manager.curr_file) {
+            let compressed_pages = CompressedPages::load(file, page_size as u64, page_size)?;
+            data_storage_manager.compressed_pages = Some(Mutex::new(compressed_pages));
+            let mut num_pages = [0; 8];
+            file.read_exact_at(&mut num_pages, NUM_PAGES_OFFSET)?;
+            tablespace.num_pages.store(u64::from_le_bytes(num_pages) as usize, Ordering::SeqCst);
+        }
+        data_storage_manager.load_tablespaces(tablespace)?;
+        if file_len > 0 && double_write_pages > 0 {
+            data_storage_manager.repair_from_double_write()?;
+        }
+        Ok(data_storage_manager)
+    }
+
+    /// Open the segment files after `file` and count the pages.
+    fn load_tablespace(&self, path: &str, file: Arc<File>, first_data_block: usize) -> Result<Tablespace, std::io::Error> {
+        let blocks = file.metadata()?.len() as usize / self.page_size;
+        let mut num_pages = blocks.saturating_sub(first_data_block);
+        let mut segments = vec![file];
+        while self.segment_pages > 0 && Path::new(&Self::segment_path(path, segments.len())).exists() {
+            let segment_path = Self::segment_path(path, segments.len());
+            let segment = Self::open_file(&segment_path, self.direct_io)?;
+            num_pages += segment.metadata()?.len() as usize / self.page_size;
+            segments.push(Arc::new(segment));
+        }
+        Ok(Tablespace {
+            path: path.to_string(),
+            segments: RwLock::new(segments),
+            num_pages: AtomicUsize::new(num_pages),
+            first_data_block,
+            extend_lock: Mutex::new(()),
+        })
+    }
+
+    /// Register tablespace 0 and every `<path>.ts<file_id>` file next to it.
+    fn load_tablespaces(&self, primary: Tablespace) -> Result<(), std::io::Error> {
+        let mut tablespaces = self.tablespaces.write().unwrap();
+        tablespaces.insert(0, Arc::new(primary));
+
+        let path = Path::new(&self.path);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let prefix = format!("{}.ts", path.file_name().unwrap().to_string_lossy());
+        for entry in std::fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            let Some(file_id) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|file_id| file_id.parse::<FileId>().ok())
+            else {
+                continue;
+            };
+            let ts_path = self.tablespace_path(file_id);
+            let file = Self::open_file(&ts_path, self.direct_io)?;
+            Self::check_header(&file, &self.tablespace_options())?;
+            let tablespace = self.load_tablespace(&ts_path, Arc::new(file), 1)?;
+            tablespaces.insert(file_id, Arc::new(tablespace));
+            self.next_file_id.fetch_max(file_id + 1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    fn tablespace_path(&self, file_id: FileId) -> String {
+        format!("{}.ts{}", self.path, file_id)
+    }
 
-        DSMgr {
-            curr_file,
-            num_pages,
+    fn segment_path(path: &str, segment: usize) -> String {
+        match segment {
+            0 => path.to_string(),
+            n => format!("{}.{}", path, n),
         }
     }
 
-    fn open_file(path: &str) -> File {
-        let curr_file = OpenOptions::new()
+    // added tablespaces share the page size and segment size of the file
+    fn tablespace_options(&self) -> StorageOptions {
+        StorageOptions {
+            sync_policy: self.sync_policy,
+            direct_io: self.direct_io,
+            page_size: self.page_size,
+            double_write_pages: 0,
+            compression: false,
+            segment_pages: self.segment_pages,
+        }
+    }
+
+    /// Create an empty tablespace and return its file id.
+    pub fn create_tablespace(&self) -> Result<FileId, std::io::Error> {
+        let file_id = self.next_file_id.fetch_add(1, Ordering::SeqCst);
+        if file_id > MAX_FILE_ID {
+            return Err(Error::new(ErrorKind::OutOfMemory, "out of file ids"));
+        }
+        let path = self.tablespace_path(file_id);
+        let file = Self::open_file(&path, self.direct_io)?;
+        if file.metadata()?.len() > 0 {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", path),
+            ));
+        }
+        Self::write_header(&file, &self.tablespace_options())?;
+        let tablespace = Tablespace {
+            path,
+            segments: RwLock::new(vec![Arc::new(file)]),
+            num_pages: AtomicUsize::new(0),
+            first_data_block: 1,
+            extend_lock: Mutex::new(()),
+        };
+        self.tablespaces.write().unwrap().insert(file_id, Arc::new(tablespace));
+        Ok(file_id)
+    }
+
+    /// Delete a tablespace with all its segment files. Tablespace 0 cannot
+    /// be dropped.
+    pub fn drop_tablespace(&self, file_id: FileId) -> Result<(), std::io::Error> {
+        if file_id == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "cannot drop tablespace 0",
+            ));
+        }
+        let tablespace = self.tablespaces.write().unwrap().remove(&file_id).ok_or_else(|| {
+            Error::new(ErrorKind::NotFound, format!("no tablespace {}", file_id))
+        })?;
+        // the last segment first, so an interrupted drop leaves a valid tablespace
+        let num_segments = tablespace.segments.read().unwrap().len();
+        for segment in (0..num_segments).rev() {
+            std::fs::remove_file(Self::segment_path(&tablespace.path, segment))?;
+        }
+        Ok(())
+    }
+
+    /// File ids of all tablespaces, in order.
+    pub fn get_tablespaces(&self) -> Vec<FileId> {
+        let mut file_ids: Vec<FileId> = self.tablespaces.read().unwrap().keys().copied().collect();
+        file_ids.sort_unstable();
+        file_ids
+    }
+
+    fn get_tablespace(&self, file_id: FileId) -> Result<Arc<Tablespace>, std::io::Error> {
+        self.tablespaces
+            .read()
+            .unwrap()
+            .get(&file_id)
+            .cloned()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no tablespace {}", file_id)))
+    }
+
+    fn open_file(path: &str, direct_io: bool) -> Result<File, std::io::Error> {
+        let mut open_options = OpenOptions::new();
+        open_options
             .read(true)
             .write(true)
             .create(true)
-            .open(&path)
-            .unwrap();
-        curr_file
+            .truncate(false);
+        if direct_io {
+            open_options.custom_flags(Self::o_direct_flag()?);
+        }
+        open_options.open(path)
+    }
+
+    fn write_header(file: &File, options: &StorageOptions) -> Result<(), std::io::Error> {
+        let mut header = AlignedBuf::new(options.page_size);
+        header[..8].copy_from_slice(MAGIC);
+        header[8..12].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&(options.page_size as u32).to_le_bytes());
+        header[16..20].copy_from_slice(&(options.double_write_pages as u32).to_le_bytes());
+        header[20..24].copy_from_slice(&(options.compression as u32).to_le_bytes());
+        header[32..40].copy_from_slice(&(options.segment_pages as u64).to_le_bytes());
+        file.write_all_at(&header, 0)?;
+        file.sync_all()
+    }
+
+    fn check_header(file: &File, options: &StorageOptions) -> Result<(), std::io::Error> {
+        let (page_size, double_write_pages) = (options.page_size, options.double_write_pages);
+        // every valid page size is a multiple of PAGE_ALIGN, so this block is
+        // inside the header and readable with direct I/O
+        let mut header = AlignedBuf::new(PAGE_ALIGN);
+        file.read_exact_at(&mut header, 0)?;
+        if &header[..8] != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a database file"));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported format version {}", version),
+            ));
+        }
+        let file_page_size = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+        if file_page_size != page_size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("file page size is {}, expected {}", file_page_size, page_size),
+            ));
+        }
+        let file_double_write_pages = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+        if file_double_write_pages != double_write_pages {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "file has {} double-write pages, expected {}",
+                    file_double_write_pages, double_write_pages
+                ),
+            ));
+        }
+        let compression = u32::from_le_bytes(header[20..24].try_into().unwrap()) != 0;
+        if compression != options.compression {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("file compression is {}, expected {}", compression, options.compression),
+            ));
+        }
+        let segment_pages = u64::from_le_bytes(header[32..40].try_into().unwrap()) as usize;
+        if segment_pages != options.segment_pages {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "file has {} pages per segment, expected {}",
+                    segment_pages, options.segment_pages
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The segment and block within it holding page `page_no` of a tablespace.
+    fn segment_block(&self, tablespace: &Tablespace, page_no: usize) -> (usize, usize) {
+        match self.segment_pages {
+            n if n == 0 || page_no < n => (0, tablespace.first_data_block + page_no),
+            n => (page_no / n, page_no % n),
+        }
+    }
+
+    /// The file and offset holding a page.
+    fn locate(&self, page_id: PageId) -> Result<(Arc<File>, u64), std::io::Error> {
+        let tablespace = self.get_tablespace(get_file_id(page_id))?;
+        let (segment, block) = self.segment_block(&tablespace, get_page_no(page_id));
+        let segments = tablespace.segments.read().unwrap();
+        let file = segments.get(segment).cloned().ok_or_else(|| {
+            Error::new(ErrorKind::UnexpectedEof, format!("page {:#x} is not allocated", page_id))
+        })?;
+        Ok((file, (block * self.page_size) as u64))
+    }
+
+    /// The file of a segment, created if the tablespace does not reach it yet.
+    fn get_segment(&self, tablespace: &Tablespace, segment: usize) -> Result<Arc<File>, std::io::Error> {
+        if let Some(file) = tablespace.segments.read().unwrap().get(segment) {
+            return Ok(file.clone());
+        }
+        let mut segments = tablespace.segments.write().unwrap();
+        while segments.len() <= segment {
+            let path = Self::segment_path(&tablespace.path, segments.len());
+            segments.push(Arc::new(Self::open_file(&path, self.direct_io)?));
+        }
+        Ok(segments[segment].clone())
+    }
+
+    /// Whether page `b` is stored right after page `a` in the same file.
+    fn is_contiguous(&self, a: PageId, b: PageId) -> bool {
+        b == a + 1
+            && get_file_id(a) == get_file_id(b)
+            && (self.segment_pages == 0 || !get_page_no(b).is_multiple_of(self.segment_pages))
+    }
+
+    fn double_write_offset(&self, slot: usize) -> u64 {
+        ((DOUBLE_WRITE_DIR_BLOCK + 1 + slot) * self.page_size) as u64
+    }
+
+    pub fn get_page_size(&self) -> usize {
+        self.page_size
+    }
+
+    #[cfg(target_os = "linux")]
+    fn o_direct_flag() -> Result<i32, std::io::Error> {
+        Ok(libc::O_DIRECT)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn o_direct_flag() -> Result<i32, std::io::Error> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "O_DIRECT is not supported on this platform",
+        ))
     }
 
     #[allow(dead_code)]
@@ -37,43 +442,275 @@ impl DSMgr {
         unimplemented!()
     }
 
-    pub fn read_page(&mut self, page_id: usize) -> Result<Data, std::io::Error> {
-        let offset = page_id * PAGE_SIZE;
-
-        self.seek(offset);
-        let mut buffer: Data = [0; PAGE_SIZE];
-        self.curr_file.read_exact(&mut buffer)?;
+    #[allow(dead_code)]
+    pub fn read_page(&self, page_id: PageId) -> Result<AlignedBuf, std::io::Error> {
+        let mut buffer = AlignedBuf::new(self.page_size);
+        self.read_page_into(page_id, &mut buffer)?;
         Ok(buffer)
     }
 
-    pub fn write_page(&mut self, page: &mut Page) -> Result<(), std::io::Error> {
-        let page_id = page.get_page_id().unwrap();
-        let offset = page_id * PAGE_SIZE;
-        let data = page.get_data();
+    /// Read a page straight into `buffer`, which must be `PAGE_ALIGN`-aligned
+    /// when the file is opened with direct I/O (frame data always is).
+    pub fn read_page_into(&self, page_id: PageId, buffer: &mut Data) -> Result<(), std::io::Error> {
+        assert_eq!(buffer.len(), self.page_size);
+        if let (Some(compressed_pages), 0) = (&self.compressed_pages, get_file_id(page_id)) {
+            return compressed_pages
+                .lock()
+                .unwrap()
+                .read(&self.curr_file, page_id, buffer);
+        }
+        let (file, offset) = self.locate(page_id)?;
+        file.read_exact_at(buffer, offset)
+    }
 
-        self.seek(offset);
-        self.curr_file.write_all(data)?;
-        self.curr_file.flush()
+    /// Write a page in place, or through the double-write area if the file
+    /// has one.
+    pub fn write_page(&self, page_id: PageId, data: &Data) -> Result<(), std::io::Error> {
+        assert_eq!(data.len(), self.page_size);
+        if let (Some(compressed_pages), 0) = (&self.compressed_pages, get_file_id(page_id)) {
+            compressed_pages
+                .lock()
+                .unwrap()
+                .write(&self.curr_file, page_id, data)?;
+            return self.sync_after_write();
+        }
+        if self.double_write_pages > 0 {
+            return self.write_pages(&[(page_id, data)]);
+        }
+        let (file, offset) = self.locate(page_id)?;
+        file.write_all_at(data, offset)?;
+        self.sync_after_write()
     }
 
-    fn seek(&mut self, offset: usize) {
+    /// Write a batch of pages: sorted by page id, each run of adjacent pages
+    /// is written with one vectored positional write (`pwritev`), and the
+    /// file is synced once.
+    /// With a double-write area, the batch goes through it in area-sized
+    /// pieces, each synced there first and then synced in place.
+    pub fn write_pages(&self, pages: &[(PageId, &Data)]) -> Result<(), std::io::Error> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+
+        let mut pages = pages.to_vec();
+        pages.sort_unstable_by_key(|(page_id, _)| *page_id);
+        if let Some(compressed_pages) = &self.compressed_pages {
+            // only tablespace 0 is compressed
+            let mut compressed_pages = compressed_pages.lock().unwrap();
+            for &(page_id, data) in pages.iter().filter(|(page_id, _)| get_file_id(*page_id) == 0) {
+                compressed_pages.write(&self.curr_file, page_id, data)?;
+            }
+            drop(compressed_pages);
+            pages.retain(|(page_id, _)| get_file_id(*page_id) != 0);
+        }
+        if self.double_write_pages > 0 {
+            let _guard = self.double_write_lock.lock().unwrap();
+            for batch in pages.chunks(self.double_write_pages) {
+                self.write_through_double_write(batch)?;
+            }
+            return Ok(());
+        }
+        for run in pages.chunk_by(|(a, _), (b, _)| self.is_contiguous(*a, *b)) {
+            let (file, offset) = self.locate(run[0].0)?;
+            let unaligned = |data: &&Data| !(data.as_ptr() as usize).is_multiple_of(PAGE_ALIGN);
+            if self.direct_io && run.iter().map(|(_, data)| data).any(unaligned) {
+                // direct I/O needs aligned buffers, so gather the run into one
+                let mut buffer = AlignedBuf::new(run.len() * self.page_size);
+                for (chunk, (_, data)) in buffer.chunks_exact_mut(self.page_size).zip(run) {
+                    chunk.copy_from_slice(data);
+                }
+                file.write_all_at(&buffer, offset)?;
+            } else {
+                let mut bufs: Vec<IoSlice> = run.iter().map(|(_, data)| IoSlice::new(data)).collect();
+                write_all_vectored_at(&file, &mut bufs, offset)?;
+            }
+        }
+        match self.sync_policy {
+            SyncPolicy::Never => Ok(()),
+            _ => self.sync_data(),
+        }
+    }
+
+    /// Write up to `double_write_pages` sorted pages, with checksums, to the
+    /// double-write area and sync, then write them in place and sync again,
+    /// so a torn in-place write can always be repaired from the copy.
+    fn write_through_double_write(&self, batch: &[(PageId, &Data)]) -> Result<(), std::io::Error> {
+        let page_size = self.page_size;
+        let mut buffer = AlignedBuf::new(batch.len() * page_size);
+        for (chunk, (_, data)) in buffer.chunks_exact_mut(page_size).zip(batch) {
+            chunk.copy_from_slice(data);
+            set_page_checksum(chunk);
+        }
+        let mut directory = AlignedBuf::new(page_size);
+        write_u32(&mut directory, 4, batch.len() as u32);
+        for (i, (page_id, _)) in batch.iter().enumerate() {
+            write_u64(&mut directory, 8 + 8 * i, *page_id as u64);
+        }
+        let checksum = compute_checksum(0, &directory[4..]);
+        write_u32(&mut directory, 0, checksum);
+        self.curr_file.write_all_at(&buffer, self.double_write_offset(0))?;
+        self.curr_file
+            .write_all_at(&directory, (DOUBLE_WRITE_DIR_BLOCK * page_size) as u64)?;
+        self.sync_data()?;
+
+        let mut start = 0;
+        for run in batch.chunk_by(|(a, _), (b, _)| self.is_contiguous(*a, *b)) {
+            let end = start + run.len();
+            let (file, offset) = self.locate(run[0].0)?;
+            file.write_all_at(&buffer[start * page_size..end * page_size], offset)?;
+            start = end;
+        }
+        self.sync_data()
+    }
+
+    /// Rewrite every page of the last double-write batch whose in-place
+    /// copy fails its checksum, i.e. was torn by a crash.
+    fn repair_from_double_write(&mut self) -> Result<(), std::io::Error> {
+        let page_size = self.page_size;
+        let mut directory = AlignedBuf::new(page_size);
         self.curr_file
-            .seek(std::io::SeekFrom::Start(offset as u64))
-            .expect("cannot seek");
+            .read_exact_at(&mut directory, (DOUBLE_WRITE_DIR_BLOCK * page_size) as u64)?;
+        let num_pages = read_u32(&directory, 4) as usize;
+        if num_pages == 0
+            || num_pages > self.double_write_pages
+            || read_u32(&directory, 0) != compute_checksum(0, &directory[4..])
+        {
+            return Ok(());
+        }
+
+        let mut copy = AlignedBuf::new(page_size);
+        let mut page = AlignedBuf::new(page_size);
+        for slot in 0..num_pages {
+            let page_id = read_u64(&directory, 8 + 8 * slot) as PageId;
+            self.curr_file.read_exact_at(&mut copy, self.double_write_offset(slot))?;
+            if !verify_page_checksum(&copy) {
+                continue;
+            }
+            // the tablespace may have been dropped since
+            let Ok((file, offset)) = self.locate(page_id) else {
+                continue;
+            };
+            let in_place = file.read_exact_at(&mut page, offset);
+            if in_place.is_err() || !verify_page_checksum(&page) {
+                file.write_all_at(&copy, offset)?;
+                self.num_repairs += 1;
+            }
+        }
+        if self.num_repairs > 0 {
+            self.sync_data()?;
+        }
+        Ok(())
     }
 
+    /// Pages repaired from the double-write area when the file was opened.
+    pub fn get_repair_num(&self) -> usize {
+        self.num_repairs
+    }
+
+    /// Bytes taken by page data on disk in all tablespaces: the live slots
+    /// of a compressed file, or every allocated page otherwise.
+    pub fn get_stored_size(&self) -> u64 {
+        let tablespaces = self.tablespaces.read().unwrap();
+        tablespaces
+            .iter()
+            .map(|(&file_id, tablespace)| match (&self.compressed_pages, file_id) {
+                (Some(compressed_pages), 0) => compressed_pages.lock().unwrap().get_stored_size(),
+                _ => (tablespace.num_pages.load(Ordering::SeqCst) * self.page_size) as u64,
+            })
+            .sum()
+    }
+
+    /// The files of every segment of every tablespace.
+    fn get_files(&self) -> Vec<Arc<File>> {
+        let tablespaces = self.tablespaces.read().unwrap();
+        tablespaces
+            .values()
+            .flat_map(|tablespace| tablespace.segments.read().unwrap().clone())
+            .collect()
+    }
+
+    /// Force all written pages and file metadata to stable storage.
     #[allow(dead_code)]
-    pub fn get_file(&self) -> &File {
-        &self.curr_file
+    pub fn sync(&self) -> Result<(), std::io::Error> {
+        for file in self.get_files() {
+            file.sync_all()?;
+        }
+        self.record_sync();
+        Ok(())
+    }
+
+    fn sync_data(&self) -> Result<(), std::io::Error> {
+        for file in self.get_files() {
+            file.sync_data()?;
+        }
+        self.record_sync();
+        Ok(())
     }
 
-    pub fn inc_num_pages(&mut self) {
-        self.num_pages += 1;
+    fn sync_after_write(&self) -> Result<(), std::io::Error> {
+        match self.sync_policy {
+            SyncPolicy::OnEveryWrite => self.sync_data(),
+            SyncPolicy::Periodic(interval) => {
+                if self.last_sync.lock().unwrap().elapsed() >= interval {
+                    self.sync_data()
+                } else {
+                    Ok(())
+                }
+            }
+            SyncPolicy::Never | SyncPolicy::OnFlushAll => Ok(()),
+        }
     }
 
+    fn record_sync(&self) {
+        *self.last_sync.lock().unwrap() = Instant::now();
+        self.num_syncs.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[allow(dead_code)]
+    pub fn get_sync_policy(&self) -> SyncPolicy {
+        self.sync_policy
+    }
+
+    /// Reserve `count` page cipher versions, see `PageCipher`, and return
+    /// the first. The header is synced before they are handed out, so they
+    /// are never handed out again, even after a crash.
+    pub fn reserve_versions(&self, count: u64) -> Result<u64, std::io::Error> {
+        let mut version_mark = self.version_mark.lock().unwrap();
+        let new_mark = (*version_mark + count).to_le_bytes();
+        if self.direct_io {
+            // rewrite the aligned block; files with direct I/O are never
+            // compressed, so nothing else updates the header meanwhile
+            let mut header = AlignedBuf::new(PAGE_ALIGN);
+            self.curr_file.read_exact_at(&mut header, 0)?;
+            header[VERSION_MARK_OFFSET..VERSION_MARK_OFFSET + 8].copy_from_slice(&new_mark);
+            self.curr_file.write_all_at(&header, 0)?;
+        } else {
+            self.curr_file.write_all_at(&new_mark, VERSION_MARK_OFFSET as u64)?;
+        }
+        self.curr_file.sync_data()?;
+        self.record_sync();
+        let first_version = *version_mark;
+        *version_mark += count;
+        Ok(first_version)
+    }
+
+    pub fn get_sync_num(&self) -> usize {
+        self.num_syncs.load(Ordering::SeqCst)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_file(&self) -> &File {
+        &self.curr_file
+    }
+
+    /// Pages allocated in tablespace 0.
     #[allow(dead_code)]
     pub fn get_num_pages(&self) -> usize {
-        self.num_pages
+        self.get_tablespace_num_pages(0).unwrap()
+    }
+
+    pub fn get_tablespace_num_pages(&self, file_id: FileId) -> Result<usize, std::io::Error> {
+        Ok(self.get_tablespace(file_id)?.num_pages.load(Ordering::SeqCst))
     }
 
     #[allow(unused_variables, dead_code)]
@@ -88,34 +725,111 @@ impl DSMgr {
         unimplemented!()
     }
 
-    pub fn new_page(&mut self) -> PageId {
-        let buffer = [0; PAGE_SIZE];
-        let new_page_id = self.num_pages;
-        self.inc_num_pages();
-        let offset = new_page_id * PAGE_SIZE;
-        self.seek(offset);
-        self.curr_file.write_all(&buffer).unwrap();
-        self.curr_file.flush().unwrap();
-        new_page_id
+    pub fn new_page(&self) -> PageId {
+        self.new_pages(1)
+    }
+
+    /// Allocate `count` zeroed pages with consecutive ids in tablespace 0
+    /// and return the first.
+    pub fn new_pages(&self, count: usize) -> PageId {
+        self.new_tablespace_pages(0, count).unwrap()
+    }
+
+    /// Allocate `count` zeroed pages with consecutive ids at the end of a
+    /// tablespace, adding segment files as needed, and return the first.
+    /// A compressed file only records the new page count. Allocations in a
+    /// tablespace run one at a time, and the new page count is published
+    /// only once the pages are written, so a failed one changes nothing.
+    pub fn new_tablespace_pages(&self, file_id: FileId, count: usize) -> Result<PageId, std::io::Error> {
+        const PAGES_PER_WRITE: usize = 256;
+
+        let tablespace = self.get_tablespace(file_id)?;
+        let _extend_guard = tablespace.extend_lock.lock().unwrap();
+        let first_page_no = tablespace.num_pages.load(Ordering::SeqCst);
+        let end_page_no = match first_page_no.checked_add(count) {
+            Some(end_page_no) if end_page_no <= get_page_no(usize::MAX) => end_page_no,
+            _ => return Err(Error::new(ErrorKind::OutOfMemory, "out of page numbers")),
+        };
+        if self.compressed_pages.is_some() && file_id == 0 {
+            self.curr_file
+                .write_all_at(&(end_page_no as u64).to_le_bytes(), NUM_PAGES_OFFSET)?;
+            self.sync_after_write()?;
+            tablespace.num_pages.store(end_page_no, Ordering::SeqCst);
+            return Ok(first_page_no);
+        }
+
+        if self.segment_pages > 0 && count > 0 {
+            // create the segment files before writing any page
+            self.get_segment(&tablespace, self.segment_block(&tablespace, end_page_no - 1).0)?;
+        }
+        let buffer = AlignedBuf::new(count.clamp(1, PAGES_PER_WRITE) * self.page_size);
+        let mut page_no = first_page_no;
+        while page_no < first_page_no + count {
+            let mut n = (first_page_no + count - page_no).min(PAGES_PER_WRITE);
+            if self.segment_pages > 0 {
+                // stop at the end of the segment
+                n = n.min(self.segment_pages - page_no % self.segment_pages);
+            }
+            let (segment, block) = self.segment_block(&tablespace, page_no);
+            self.get_segment(&tablespace, segment)?
+                .write_all_at(&buffer[..n * self.page_size], (block * self.page_size) as u64)?;
+            page_no += n;
+        }
+        self.sync_after_write()?;
+        tablespace.num_pages.store(end_page_no, Ordering::SeqCst);
+        Ok(make_page_id(file_id, first_page_no))
     }
 }
 
+/// Write `bufs` back to back at `offset` with `pwritev`, retrying on short
+/// writes.
+fn write_all_vectored_at(file: &File, mut bufs: &mut [IoSlice], mut offset: u64) -> Result<(), std::io::Error> {
+    while !bufs.is_empty() {
+        // IoSlice is ABI compatible with iovec on unix
+        let written = unsafe {
+            libc::pwritev(
+                file.as_raw_fd(),
+                bufs.as_ptr() as *const libc::iovec,
+                bufs.len().min(IOV_MAX) as libc::c_int,
+                offset as libc::off_t,
+            )
+        };
+        if written < 0 {
+            let err = Error::last_os_error();
+            if err.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if written == 0 {
+            return Err(ErrorKind::WriteZero.into());
+        }
+        IoSlice::advance_slices(&mut bufs, written as usize);
+        offset += written as u64;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn test_page(page_size: usize, text: &str) -> AlignedBuf {
+        let mut data = AlignedBuf::new(page_size);
+        data[..text.len()].copy_from_slice(text.as_bytes());
+        data
+    }
+
     #[test]
     fn test_data_storage_manager() {
         let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
-        let mut disk_manager = DSMgr::new(&file);
+        let disk_manager = DSMgr::new(&file);
 
         for i in 0..100 {
             let page_id = disk_manager.new_page();
             assert_eq!(page_id, i);
-            let mut page = Page::new(Some(i));
-            let test_data = format!("test data: {}", i);
-            page.get_data()[..test_data.len()].copy_from_slice(test_data.as_bytes());
-            disk_manager.write_page(&mut page).unwrap();
+            let data = test_page(PAGE_SIZE, &format!("test data: {}", i));
+            disk_manager.write_page(i, &data).unwrap();
         }
         assert_eq!(disk_manager.get_num_pages(), 100);
 
@@ -127,4 +841,359 @@ mod test {
         assert_eq!(disk_manager.get_num_pages(), 100);
         let _ = std::fs::remove_file(file);
     }
+
+    #[test]
+    fn test_data_storage_manager_write_pages() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let disk_manager = DSMgr::new(&file);
+        assert_eq!(disk_manager.new_pages(20), 0);
+
+        // two runs (3..=6, 10..=11) and two single pages, given out of order
+        let page_ids = [11, 5, 3, 17, 6, 10, 4, 0];
+        let datas: Vec<AlignedBuf> = page_ids
+            .iter()
+            .map(|i| test_page(PAGE_SIZE, &format!("batch data: {}", i)))
+            .collect();
+        let pages: Vec<(PageId, &Data)> = page_ids.iter().copied().zip(datas.iter().map(|d| &d[..])).collect();
+        disk_manager.write_pages(&pages).unwrap();
+
+        for i in 0..20 {
+            let data = disk_manager.read_page(i).unwrap();
+            if page_ids.contains(&i) {
+                let test_target_data = format!("batch data: {}", i);
+                assert_eq!(&data[..test_target_data.len()], test_target_data.as_bytes());
+            } else {
+                assert!(data.iter().all(|&b| b == 0));
+            }
+        }
+
+        // a run longer than one pwritev call takes
+        disk_manager.new_pages(IOV_MAX + 10);
+        let datas: Vec<AlignedBuf> = (0..IOV_MAX + 10)
+            .map(|i| test_page(PAGE_SIZE, &format!("long run: {}", i)))
+            .collect();
+        let pages: Vec<(PageId, &Data)> = datas.iter().enumerate().map(|(i, d)| (20 + i, &d[..])).collect();
+        disk_manager.write_pages(&pages).unwrap();
+        for i in [0, IOV_MAX - 1, IOV_MAX, IOV_MAX + 9] {
+            let test_target_data = format!("long run: {}", i);
+            let data = disk_manager.read_page(20 + i).unwrap();
+            assert_eq!(&data[..test_target_data.len()], test_target_data.as_bytes());
+        }
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_data_storage_manager_concurrent() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let disk_manager = DSMgr::new(&file);
+        disk_manager.new_pages(64);
+
+        // each thread owns the pages congruent to its index
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                let disk_manager = &disk_manager;
+                s.spawn(move || {
+                    for i in (t..64).step_by(4) {
+                        let test_data = format!("thread {} page {}", t, i);
+                        disk_manager.write_page(i, &test_page(PAGE_SIZE, &test_data)).unwrap();
+                        let data = disk_manager.read_page(i).unwrap();
+                        assert_eq!(&data[..test_data.len()], test_data.as_bytes());
+                    }
+                });
+            }
+        });
+
+        for i in 0..64 {
+            let data = disk_manager.read_page(i).unwrap();
+            let test_target_data = format!("thread {} page {}", i % 4, i);
+            assert_eq!(&data[..test_target_data.len()], test_target_data.as_bytes());
+        }
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_data_storage_manager_sync_policy() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let data = AlignedBuf::new(PAGE_SIZE);
+
+        let disk_manager = DSMgr::with_sync_policy(&file, SyncPolicy::Never);
+        disk_manager.new_page();
+        disk_manager.write_page(0, &data).unwrap();
+        disk_manager.write_pages(&[(0, &data)]).unwrap();
+        assert_eq!(disk_manager.get_sync_num(), 0);
+        disk_manager.sync().unwrap();
+        assert_eq!(disk_manager.get_sync_num(), 1);
+
+        let disk_manager = DSMgr::with_sync_policy(&file, SyncPolicy::OnFlushAll);
+        disk_manager.write_page(0, &data).unwrap();
+        assert_eq!(disk_manager.get_sync_num(), 0);
+        disk_manager.write_pages(&[(0, &data)]).unwrap();
+        assert_eq!(disk_manager.get_sync_num(), 1);
+
+        let disk_manager = DSMgr::with_sync_policy(&file, SyncPolicy::OnEveryWrite);
+        disk_manager.new_page();
+        disk_manager.write_page(0, &data).unwrap();
+        disk_manager.write_pages(&[(0, &data)]).unwrap();
+        assert_eq!(disk_manager.get_sync_num(), 3);
+
+        let interval = std::time::Duration::from_millis(50);
+        let disk_manager = DSMgr::with_sync_policy(&file, SyncPolicy::Periodic(interval));
+        disk_manager.write_page(0, &data).unwrap();
+        assert_eq!(disk_manager.get_sync_num(), 0);
+        std::thread::sleep(interval);
+        disk_manager.write_page(0, &data).unwrap();
+        assert_eq!(disk_manager.get_sync_num(), 1);
+        let _ = std::fs::remove_file(file);
+    }
+
+    // needs a file system that supports O_DIRECT under ./target, which
+    // e.g. tmpfs on older kernels does not
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_data_storage_manager_direct_io() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let options = StorageOptions {
+            direct_io: true,
+            ..Default::default()
+        };
+        let disk_manager = DSMgr::open(&file, options).unwrap();
+        let flags = unsafe { libc::fcntl(disk_manager.get_file().as_raw_fd(), libc::F_GETFL) };
+        assert_ne!(flags & libc::O_DIRECT, 0);
+
+        assert_eq!(disk_manager.new_pages(8), 0);
+        let page_ids = [1, 2, 3, 6];
+        let datas: Vec<AlignedBuf> = page_ids
+            .iter()
+            .map(|i| test_page(PAGE_SIZE, &format!("direct data: {}", i)))
+            .collect();
+        disk_manager.write_page(1, &datas[0]).unwrap();
+        let pages: Vec<(PageId, &Data)> = page_ids.iter().copied().zip(datas.iter().map(|d| &d[..])).collect();
+        disk_manager.write_pages(&pages).unwrap();
+
+        let mut frame = AlignedBuf::new(PAGE_SIZE);
+        for i in page_ids {
+            disk_manager.read_page_into(i, &mut frame).unwrap();
+            let test_target_data = format!("direct data: {}", i);
+            assert_eq!(&frame[..test_target_data.len()], test_target_data.as_bytes());
+        }
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_data_storage_manager_page_size() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let options = StorageOptions {
+            page_size: 16384,
+            ..Default::default()
+        };
+        {
+            let disk_manager = DSMgr::open(&file, options).unwrap();
+            assert_eq!(disk_manager.get_page_size(), 16384);
+            assert_eq!(disk_manager.new_pages(3), 0);
+            let mut data = test_page(16384, "first");
+            data[16384 - 4..].copy_from_slice(b"last");
+            disk_manager.write_page(2, &data).unwrap();
+        }
+
+        // the page size is stored in the header and checked on reopen
+        let disk_manager = DSMgr::open(&file, options).unwrap();
+        assert_eq!(disk_manager.get_num_pages(), 3);
+        let data = disk_manager.read_page(2).unwrap();
+        assert_eq!(data.len(), 16384);
+        assert_eq!(&data[..5], b"first");
+        assert_eq!(&data[16384 - 4..], b"last");
+        drop(disk_manager);
+
+        let err = DSMgr::open(&file, StorageOptions::default()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let invalid = StorageOptions {
+            page_size: 6000,
+            ..Default::default()
+        };
+        let err = DSMgr::open(&file, invalid).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_data_storage_manager_double_write() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let options = StorageOptions {
+            double_write_pages: 4,
+            ..Default::default()
+        };
+        let page_with = |text: &str| {
+            let mut data = AlignedBuf::new(PAGE_SIZE);
+            data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + text.len()].copy_from_slice(text.as_bytes());
+            data
+        };
+        {
+            let disk_manager = DSMgr::open(&file, options).unwrap();
+            assert_eq!(disk_manager.new_pages(10), 0);
+            // six pages go through the area in two batches
+            let datas: Vec<AlignedBuf> = (0..6).map(|i| page_with(&format!("page {}", i))).collect();
+            let pages: Vec<(PageId, &Data)> = (0..6).zip(datas.iter().map(|d| &d[..])).collect();
+            disk_manager.write_pages(&pages).unwrap();
+            let syncs = disk_manager.get_sync_num();
+            assert_eq!(syncs, 4);
+            disk_manager.write_page(8, &page_with("page 8")).unwrap();
+            assert_eq!(disk_manager.get_sync_num(), syncs + 2);
+            assert!(verify_page_checksum(&disk_manager.read_page(8).unwrap()));
+
+            // tear page 8: its second half never made it to disk
+            let torn = [0xab; PAGE_SIZE / 2];
+            let (file, offset) = disk_manager.locate(8).unwrap();
+            file.write_all_at(&torn, offset + (PAGE_SIZE / 2) as u64).unwrap();
+            assert!(!verify_page_checksum(&disk_manager.read_page(8).unwrap()));
+        }
+
+        let disk_manager = DSMgr::open(&file, options).unwrap();
+        assert_eq!(disk_manager.get_repair_num(), 1);
+        assert_eq!(disk_manager.get_num_pages(), 10);
+        let data = disk_manager.read_page(8).unwrap();
+        assert!(verify_page_checksum(&data));
+        assert_eq!(&data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 6], b"page 8");
+        for i in 0..6 {
+            let data = disk_manager.read_page(i).unwrap();
+            let text = format!("page {}", i);
+            assert_eq!(&data[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + text.len()], text.as_bytes());
+        }
+        drop(disk_manager);
+
+        // the area size is part of the file layout
+        let err = DSMgr::open(&file, StorageOptions::default()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_data_storage_manager_compression() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let options = StorageOptions {
+            compression: true,
+            ..Default::default()
+        };
+        let mut noise = test_page(PAGE_SIZE, "noise");
+        for (i, b) in noise.iter_mut().enumerate().skip(5) {
+            *b = (i * 7919 % 251) as u8;
+        }
+        {
+            let disk_manager = DSMgr::open(&file, options).unwrap();
+            assert_eq!(disk_manager.new_pages(1000), 0);
+            assert_eq!(disk_manager.get_stored_size(), 0);
+            assert!(disk_manager.new_tablespace_pages(0, get_page_no(usize::MAX)).is_err());
+            assert_eq!(disk_manager.get_num_pages(), 1000);
+            assert_eq!(&disk_manager.read_page(999).unwrap()[..], &[0; PAGE_SIZE][..]);
+
+            let datas: Vec<AlignedBuf> = (0..100).map(|i| test_page(PAGE_SIZE, &format!("page {}", i))).collect();
+            let pages: Vec<(PageId, &Data)> = (0..100).zip(datas.iter().map(|d| &d[..])).collect();
+            disk_manager.write_pages(&pages).unwrap();
+            disk_manager.write_page(500, &noise).unwrap();
+            // a rewrite goes to a new slot and frees the old one for the next
+            disk_manager.write_page(7, &test_page(PAGE_SIZE, "page 7 again")).unwrap();
+            let size = disk_manager.get_file().metadata().unwrap().len();
+            disk_manager.write_page(8, &test_page(PAGE_SIZE, "page 8 again")).unwrap();
+            assert_eq!(disk_manager.get_file().metadata().unwrap().len(), size);
+        }
+
+        let disk_manager = DSMgr::open(&file, options).unwrap();
+        assert_eq!(disk_manager.get_num_pages(), 1000);
+        for i in 0..100 {
+            let text = match i {
+                7 | 8 => format!("page {} again", i),
+                _ => format!("page {}", i),
+            };
+            assert_eq!(&disk_manager.read_page(i).unwrap()[..], &test_page(PAGE_SIZE, &text)[..]);
+        }
+        assert_eq!(&disk_manager.read_page(500).unwrap()[..], &noise[..]);
+        // 100 small slots plus one raw page, instead of 1000 pages
+        let size = disk_manager.get_file().metadata().unwrap().len();
+        assert!(size < (PAGE_SIZE * 5) as u64 + 100 * 128);
+        assert!(disk_manager.get_stored_size() <= size);
+        drop(disk_manager);
+
+        let err = DSMgr::open(&file, StorageOptions::default()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let invalid = StorageOptions {
+            direct_io: true,
+            ..options
+        };
+        let err = DSMgr::open(&file, invalid).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn test_data_storage_manager_tablespaces() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let options = StorageOptions {
+            segment_pages: 4,
+            ..Default::default()
+        };
+        let ts_file = {
+            let disk_manager = DSMgr::open(&file, options).unwrap();
+            assert_eq!(disk_manager.get_tablespaces(), vec![0]);
+            // ten pages spill into two segment files
+            assert_eq!(disk_manager.new_pages(10), 0);
+            for i in 0..10 {
+                disk_manager.write_page(i, &test_page(PAGE_SIZE, &format!("page {}", i))).unwrap();
+            }
+            assert!(std::path::Path::new(&format!("{}.2", file)).exists());
+            assert_eq!(disk_manager.get_file().metadata().unwrap().len(), (5 * PAGE_SIZE) as u64);
+
+            let file_id = disk_manager.create_tablespace().unwrap();
+            assert_eq!(file_id, 1);
+            let first_page_id = disk_manager.new_tablespace_pages(file_id, 6).unwrap();
+            assert_eq!(first_page_id, make_page_id(1, 0));
+            assert_eq!((get_file_id(first_page_id), get_page_no(first_page_id)), (1, 0));
+            // a batch across both tablespaces and a segment boundary
+            let page_ids = [2, 3, 4, make_page_id(1, 3), make_page_id(1, 4)];
+            let datas: Vec<AlignedBuf> = page_ids
+                .iter()
+                .map(|i| test_page(PAGE_SIZE, &format!("batch {:#x}", i)))
+                .collect();
+            let pages: Vec<(PageId, &Data)> = page_ids.iter().copied().zip(datas.iter().map(|d| &d[..])).collect();
+            disk_manager.write_pages(&pages).unwrap();
+
+            // a failed allocation leaves the page count as it was
+            let ts_file = disk_manager.tablespace_path(file_id);
+            std::fs::create_dir(format!("{}.2", ts_file)).unwrap();
+            assert!(disk_manager.new_tablespace_pages(file_id, 4).is_err());
+            std::fs::remove_dir(format!("{}.2", ts_file)).unwrap();
+            assert!(disk_manager.new_tablespace_pages(file_id, usize::MAX).is_err());
+            assert_eq!(disk_manager.get_tablespace_num_pages(file_id).unwrap(), 6);
+            ts_file
+        };
+
+        let disk_manager = DSMgr::open(&file, options).unwrap();
+        assert_eq!(disk_manager.get_tablespaces(), vec![0, 1]);
+        assert_eq!(disk_manager.get_num_pages(), 10);
+        assert_eq!(disk_manager.get_tablespace_num_pages(1).unwrap(), 6);
+        for i in [0, 1, 5, 9] {
+            let text = format!("page {}", i);
+            assert_eq!(&disk_manager.read_page(i).unwrap()[..text.len()], text.as_bytes());
+        }
+        for i in [2, 3, 4, make_page_id(1, 3), make_page_id(1, 4)] {
+            let text = format!("batch {:#x}", i);
+            assert_eq!(&disk_manager.read_page(i).unwrap()[..text.len()], text.as_bytes());
+        }
+        assert!(disk_manager.read_page(make_page_id(2, 0)).is_err());
+
+        // dropping removes the tablespace with its segments; ids are not reused
+        assert_eq!(disk_manager.drop_tablespace(0).unwrap_err().kind(), ErrorKind::InvalidInput);
+        disk_manager.drop_tablespace(1).unwrap();
+        assert!(!std::path::Path::new(&ts_file).exists());
+        assert!(!std::path::Path::new(&format!("{}.1", ts_file)).exists());
+        assert!(disk_manager.read_page(make_page_id(1, 0)).is_err());
+        assert_eq!(disk_manager.create_tablespace().unwrap(), 2);
+        disk_manager.drop_tablespace(2).unwrap();
+        drop(disk_manager);
+
+        // the segment size is part of the file layout
+        let err = DSMgr::open(&file, StorageOptions::default()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        for path in [file.clone(), format!("{}.1", file), format!("{}.2", file)] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
 }
@@ -0,0 +1,278 @@
+use std::io::{Error, ErrorKind};
+
+use crate::config::*;
+use crate::data_storage_manager::DSMgr;
+use crate::page::*;
+
+pub type Key = [u8; 32];
+
+/// Where the 256-bit page encryption key comes from.
+pub trait KeyProvider {
+    fn get_key(&self) -> Result<Key, std::io::Error>;
+}
+
+/// Reads the key from a file holding 32 raw bytes or 64 hex digits.
+pub struct FileKeyProvider {
+    path: String,
+}
+
+impl FileKeyProvider {
+    pub fn new(path: &str) -> FileKeyProvider {
+        FileKeyProvider {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn get_key(&self) -> Result<Key, std::io::Error> {
+        let bytes = std::fs::read(&self.path)?;
+        if let Ok(key) = bytes.as_slice().try_into() {
+            return Ok(key);
+        }
+        parse_hex_key(std::str::from_utf8(&bytes).unwrap_or_default().trim())
+    }
+}
+
+/// Reads the key as 64 hex digits from an environment variable, for tests.
+pub struct EnvKeyProvider {
+    var: String,
+}
+
+impl EnvKeyProvider {
+    pub fn new(var: &str) -> EnvKeyProvider {
+        EnvKeyProvider {
+            var: var.to_string(),
+        }
+    }
+}
+
+impl KeyProvider for EnvKeyProvider {
+    fn get_key(&self) -> Result<Key, std::io::Error> {
+        let hex = std::env::var(&self.var).map_err(|e| Error::new(ErrorKind::NotFound, e))?;
+        parse_hex_key(hex.trim())
+    }
+}
+
+fn parse_hex_key(hex: &str) -> Result<Key, std::io::Error> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "key must be 32 bytes or 64 hex digits");
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut key = [0; 32];
+    for (i, b) in key.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(key)
+}
+
+/// Encrypts page bodies with XChaCha20 between `BMgr` and `DSMgr`. The
+/// page header stays in plain text: it carries the write version, which with
+/// the full 64-bit page id forms the 24-byte nonce. Every write takes a new
+/// version, so no nonce is used twice under one key. Versions are reserved
+/// in batches from a high-water mark in the file header, see
+/// `DSMgr::reserve_versions`, so they keep growing across restarts.
+pub struct PageCipher {
+    key: [u32; 8],
+    next_version: u64,
+    // the end of the versions reserved so far
+    reserved_version: u64,
+}
+
+const VERSION_BATCH: u64 = 1 << 16;
+
+impl PageCipher {
+    pub fn new(key_provider: &dyn KeyProvider) -> Result<PageCipher, std::io::Error> {
+        let key = key_provider.get_key()?;
+        Ok(PageCipher {
+            key: std::array::from_fn(|i| u32::from_le_bytes(key[4 * i..4 * i + 4].try_into().unwrap())),
+            next_version: 0,
+            reserved_version: 0,
+        })
+    }
+
+    /// Encrypt a page in place before it is written to `data_storage_manager`.
+    pub fn encrypt(
+        &mut self,
+        data_storage_manager: &DSMgr,
+        page_id: PageId,
+        data: &mut Data,
+    ) -> Result<(), std::io::Error> {
+        if self.next_version == self.reserved_version {
+            self.next_version = data_storage_manager.reserve_versions(VERSION_BATCH)?;
+            self.reserved_version = self.next_version + VERSION_BATCH;
+        }
+        let version = self.next_version;
+        self.next_version += 1;
+        write_u64(data, PAGE_VERSION_OFFSET, version);
+        self.apply(page_id, data);
+        Ok(())
+    }
+
+    /// Decrypt a page in place after it is read. A page that was never
+    /// written has version 0 and is all zeros.
+    pub fn decrypt(&self, page_id: PageId, data: &mut Data) {
+        if read_u64(data, PAGE_VERSION_OFFSET) != 0 {
+            self.apply(page_id, data);
+        }
+    }
+
+    /// XChaCha20 with the nonce `page_id | version | 0`: HChaCha20 derives a
+    /// subkey from the first 16 bytes, and ChaCha20 runs under it with the
+    /// last 8 bytes, all zero here.
+    fn apply(&self, page_id: PageId, data: &mut Data) {
+        let mut nonce = [0; 16];
+        nonce[..8].copy_from_slice(&(page_id as u64).to_le_bytes());
+        nonce[8..].copy_from_slice(&data[PAGE_VERSION_OFFSET..PAGE_VERSION_OFFSET + 8]);
+        let subkey = hchacha20(&self.key, &nonce);
+        chacha20_xor(&subkey, &[0; 12], 0, &mut data[PAGE_HEADER_SIZE..]);
+    }
+}
+
+const CONSTANTS: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+fn le_words<const N: usize>(bytes: &[u8]) -> [u32; N] {
+    std::array::from_fn(|i| u32::from_le_bytes(bytes[4 * i..4 * i + 4].try_into().unwrap()))
+}
+
+/// The 20 ChaCha20 rounds, without the final addition of the input.
+fn chacha20_rounds(state: &mut [u32; 16]) {
+    for _ in 0..10 {
+        quarter_round(state, 0, 4, 8, 12);
+        quarter_round(state, 1, 5, 9, 13);
+        quarter_round(state, 2, 6, 10, 14);
+        quarter_round(state, 3, 7, 11, 15);
+        quarter_round(state, 0, 5, 10, 15);
+        quarter_round(state, 1, 6, 11, 12);
+        quarter_round(state, 2, 7, 8, 13);
+        quarter_round(state, 3, 4, 9, 14);
+    }
+}
+
+/// Derive an XChaCha20 subkey from the key and 16 nonce bytes.
+fn hchacha20(key: &[u32; 8], nonce: &[u8; 16]) -> [u32; 8] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&CONSTANTS);
+    state[4..12].copy_from_slice(key);
+    state[12..].copy_from_slice(&le_words::<4>(nonce));
+    chacha20_rounds(&mut state);
+    let mut subkey = [0; 8];
+    subkey[..4].copy_from_slice(&state[..4]);
+    subkey[4..].copy_from_slice(&state[12..]);
+    subkey
+}
+
+/// XOR `data` with the ChaCha20 (RFC 8439) key stream starting at `counter`.
+fn chacha20_xor(key: &[u32; 8], nonce: &[u8; 12], mut counter: u32, data: &mut [u8]) {
+    let nonce: [u32; 3] = le_words(nonce);
+
+    for chunk in data.chunks_mut(64) {
+        let mut input = [0u32; 16];
+        input[..4].copy_from_slice(&CONSTANTS);
+        input[4..12].copy_from_slice(key);
+        input[12] = counter;
+        input[13..].copy_from_slice(&nonce);
+
+        let mut state = input;
+        chacha20_rounds(&mut state);
+        let key_stream = state
+            .iter()
+            .zip(input)
+            .flat_map(|(word, input)| word.wrapping_add(input).to_le_bytes());
+        for (b, k) in chunk.iter_mut().zip(key_stream) {
+            *b ^= k;
+        }
+        counter = counter.wrapping_add(1);
+    }
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chacha20_test() {
+        // RFC 8439, section 2.4.2
+        let key: [u32; 8] = std::array::from_fn(|i| {
+            u32::from_le_bytes([4 * i as u8, 4 * i as u8 + 1, 4 * i as u8 + 2, 4 * i as u8 + 3])
+        });
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        let plain_text = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        let mut data = plain_text.to_vec();
+        chacha20_xor(&key, &nonce, 1, &mut data);
+        let expected = [
+            0x6e, 0x2e, 0x35, 0x9a, 0x25, 0x68, 0xf9, 0x80, 0x41, 0xba, 0x07, 0x28, 0xdd, 0x0d,
+            0x69, 0x81, 0xe9, 0x7e, 0x7a, 0xec, 0x1d, 0x43, 0x60, 0xc2, 0x0a, 0x27, 0xaf, 0xcc,
+        ];
+        assert_eq!(&data[..expected.len()], &expected);
+        assert_eq!(&data[data.len() - 2..], &[0x87, 0x4d]);
+        chacha20_xor(&key, &nonce, 1, &mut data);
+        assert_eq!(data, plain_text);
+
+        // draft-irtf-cfrg-xchacha, section 2.2.1
+        let nonce = [0, 0, 0, 0x09, 0, 0, 0, 0x4a, 0, 0, 0, 0, 0x31, 0x41, 0x59, 0x27];
+        let subkey: Vec<u8> = hchacha20(&key, &nonce).iter().flat_map(|w| w.to_le_bytes()).collect();
+        let expected = [
+            0x82, 0x41, 0x3b, 0x42, 0x27, 0xb2, 0x7b, 0xfe, 0xd3, 0x0e, 0x42, 0x50, 0x8a, 0x87, 0x7d, 0x73,
+            0xa0, 0xf9, 0xe4, 0xd5, 0x8a, 0x74, 0xa8, 0x53, 0xc1, 0x2e, 0xc4, 0x13, 0x26, 0xd3, 0xec, 0xdc,
+        ];
+        assert_eq!(subkey, expected);
+    }
+
+    #[test]
+    fn page_cipher_test() {
+        let key_file = format!("./target/test_file_{:?}.key", std::thread::current().id());
+        std::fs::write(&key_file, "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff\n").unwrap();
+        let mut cipher = PageCipher::new(&FileKeyProvider::new(&key_file)).unwrap();
+        let mut restarted_cipher = PageCipher::new(&FileKeyProvider::new(&key_file)).unwrap();
+        let _ = std::fs::remove_file(&key_file);
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let data_storage_manager = DSMgr::new(&file);
+
+        let mut page = vec![0; PAGE_SIZE];
+        page[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 6].copy_from_slice(b"secret");
+        let plain_text = page.clone();
+        cipher.encrypt(&data_storage_manager, 3, &mut page).unwrap();
+        let first = page.clone();
+        assert!(!first.windows(6).any(|w| w == b"secret"));
+        // the file id is part of the nonce
+        let mut other = first.clone();
+        cipher.decrypt(make_page_id(1, 3), &mut other);
+        assert_ne!(&other[PAGE_HEADER_SIZE..], &plain_text[PAGE_HEADER_SIZE..]);
+        cipher.decrypt(3, &mut page);
+        assert_eq!(&page[PAGE_HEADER_SIZE..], &plain_text[PAGE_HEADER_SIZE..]);
+
+        // the same page written again gets a new nonce
+        cipher.encrypt(&data_storage_manager, 3, &mut page).unwrap();
+        assert_ne!(&page[PAGE_HEADER_SIZE..], &first[PAGE_HEADER_SIZE..]);
+        let version = read_u64(&page, PAGE_VERSION_OFFSET);
+        // versions continue after the reserved ones on restart
+        drop(data_storage_manager);
+        let data_storage_manager = DSMgr::new(&file);
+        restarted_cipher.encrypt(&data_storage_manager, 3, &mut page).unwrap();
+        assert_eq!(read_u64(&page, PAGE_VERSION_OFFSET), version - 1 + VERSION_BATCH);
+        drop(data_storage_manager);
+        let _ = std::fs::remove_file(&file);
+        // a page never written reads as zeros
+        let mut zeros = vec![0; PAGE_SIZE];
+        cipher.decrypt(4, &mut zeros);
+        assert!(zeros.iter().all(|&b| b == 0));
+
+        assert_eq!(
+            FileKeyProvider::new(&key_file).get_key().unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+    }
+}
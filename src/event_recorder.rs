@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+
+use crate::config::*;
+
+const MAGIC: &[u8; 8] = b"ADBSEVT\0";
+const EVENT_SIZE: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// `fix_page` found the page in the pool.
+    Hit,
+    /// `fix_page` read the page into a frame.
+    Miss,
+    /// `fix_new_page` put a new page into a frame.
+    New,
+    /// A page left its frame; `dirty` tells whether it was written back.
+    Evict,
+    /// A dirty page was written back.
+    Flush,
+    Pin,
+    Unpin,
+}
+
+impl EventKind {
+    const ALL: [EventKind; 7] = [
+        EventKind::Hit,
+        EventKind::Miss,
+        EventKind::New,
+        EventKind::Evict,
+        EventKind::Flush,
+        EventKind::Pin,
+        EventKind::Unpin,
+    ];
+
+    fn from_u8(value: u8) -> Option<EventKind> {
+        Self::ALL.get(value as usize).copied()
+    }
+}
+
+/// One buffer pool event, stored as 24 bytes little endian:
+/// | seq: u64 | page_id: u64 | frame_id: u32 | kind: u8 | dirty: u8 | pin_count: u16 |
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub seq: u64,
+    pub kind: EventKind,
+    pub page_id: PageId,
+    pub frame_id: FrameId,
+    pub dirty: bool,
+    /// The pin count after a pin or unpin.
+    pub pin_count: u16,
+}
+
+impl Event {
+    fn encode(&self) -> [u8; EVENT_SIZE] {
+        let mut buf = [0; EVENT_SIZE];
+        buf[..8].copy_from_slice(&self.seq.to_le_bytes());
+        buf[8..16].copy_from_slice(&(self.page_id as u64).to_le_bytes());
+        buf[16..20].copy_from_slice(&(self.frame_id as u32).to_le_bytes());
+        buf[20] = self.kind as u8;
+        buf[21] = self.dirty as u8;
+        buf[22..].copy_from_slice(&self.pin_count.to_le_bytes());
+        buf
+    }
+
+    fn decode(buf: &[u8; EVENT_SIZE]) -> Option<Event> {
+        Some(Event {
+            seq: u64::from_le_bytes(buf[..8].try_into().unwrap()),
+            page_id: u64::from_le_bytes(buf[8..16].try_into().unwrap()) as PageId,
+            frame_id: u32::from_le_bytes(buf[16..20].try_into().unwrap()) as FrameId,
+            kind: EventKind::from_u8(buf[20])?,
+            dirty: buf[21] != 0,
+            pin_count: u16::from_le_bytes(buf[22..].try_into().unwrap()),
+        })
+    }
+}
+
+/// Appends the events of a `BMgr` to a binary event log, numbered in order.
+/// Set it with `BMgr::set_event_recorder`; `read_events` and `summarize`
+/// read the log back.
+pub struct EventRecorder {
+    writer: BufWriter<File>,
+    next_seq: u64,
+}
+
+impl EventRecorder {
+    /// Create or truncate the event log at `path`.
+    pub fn create(path: &str) -> Result<EventRecorder, std::io::Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        Ok(EventRecorder { writer, next_seq: 0 })
+    }
+
+    pub fn record(
+        &mut self,
+        kind: EventKind,
+        page_id: PageId,
+        frame_id: FrameId,
+        dirty: bool,
+        pin_count: usize,
+    ) -> Result<(), std::io::Error> {
+        let event = Event {
+            seq: self.next_seq,
+            kind,
+            page_id,
+            frame_id,
+            dirty,
+            pin_count: pin_count.min(u16::MAX as usize) as u16,
+        };
+        self.next_seq += 1;
+        self.writer.write_all(&event.encode())
+    }
+
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.writer.flush()
+    }
+}
+
+/// Read an event log. A torn last event is ignored.
+pub fn read_events(path: &str) -> Result<Vec<Event>, std::io::Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not an event log"));
+    }
+    let mut events = Vec::new();
+    let mut buf = [0; EVENT_SIZE];
+    loop {
+        match reader.read_exact(&mut buf) {
+            Ok(()) => events.push(
+                Event::decode(&buf).ok_or_else(|| Error::new(ErrorKind::InvalidData, "bad event kind"))?,
+            ),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(events),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// What `summarize` finds in an event log.
+#[derive(Debug, Default)]
+pub struct EventSummary {
+    pub counts: HashMap<EventKind, usize>,
+    /// Evictions by the number of events the page stayed in the pool,
+    /// bucket `i` counting ages in `[2^i, 2^(i+1))`.
+    pub eviction_ages: Vec<usize>,
+    /// Evictions followed by a miss on the same page within the window.
+    pub regrets: usize,
+    pub regret_window: u64,
+    /// Pages re-read most often soon after their eviction, most first.
+    pub top_regret_pages: Vec<(PageId, usize)>,
+    /// Pages written back on eviction most often, most first.
+    pub top_dirty_evictions: Vec<(PageId, usize)>,
+}
+
+const TOP_PAGES: usize = 10;
+
+/// Summarize an event log: eviction ages, pages evicted and read again
+/// within `regret_window` events, and pages most often evicted dirty.
+pub fn summarize(events: &[Event], regret_window: u64) -> EventSummary {
+    let mut summary = EventSummary {
+        regret_window,
+        ..Default::default()
+    };
+    // page -> seq it was brought in, and seq it was evicted
+    let mut loaded_at: HashMap<PageId, u64> = HashMap::new();
+    let mut evicted_at: HashMap<PageId, u64> = HashMap::new();
+    let mut regrets: HashMap<PageId, usize> = HashMap::new();
+    let mut dirty_evictions: HashMap<PageId, usize> = HashMap::new();
+
+    for event in events {
+        *summary.counts.entry(event.kind).or_default() += 1;
+        match event.kind {
+            EventKind::Miss | EventKind::New => {
+                if let Some(evicted) = evicted_at.remove(&event.page_id) {
+                    if event.kind == EventKind::Miss && event.seq - evicted <= regret_window {
+                        summary.regrets += 1;
+                        *regrets.entry(event.page_id).or_default() += 1;
+                    }
+                }
+                loaded_at.insert(event.page_id, event.seq);
+            }
+            EventKind::Evict => {
+                if let Some(loaded) = loaded_at.remove(&event.page_id) {
+                    let age = (event.seq - loaded).max(1);
+                    let bucket = age.ilog2() as usize;
+                    if summary.eviction_ages.len() <= bucket {
+                        summary.eviction_ages.resize(bucket + 1, 0);
+                    }
+                    summary.eviction_ages[bucket] += 1;
+                }
+                evicted_at.insert(event.page_id, event.seq);
+                if event.dirty {
+                    *dirty_evictions.entry(event.page_id).or_default() += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    summary.top_regret_pages = top_pages(regrets);
+    summary.top_dirty_evictions = top_pages(dirty_evictions);
+    summary
+}
+
+fn top_pages(counts: HashMap<PageId, usize>) -> Vec<(PageId, usize)> {
+    let mut pages: Vec<(PageId, usize)> = counts.into_iter().collect();
+    pages.sort_unstable_by_key(|&(page_id, count)| (std::cmp::Reverse(count), page_id));
+    pages.truncate(TOP_PAGES);
+    pages
+}
+
+impl fmt::Display for EventSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = |kind| self.counts.get(&kind).copied().unwrap_or(0);
+        writeln!(f, "events:")?;
+        for kind in EventKind::ALL {
+            writeln!(f, "  {:?}: {}", kind, count(kind))?;
+        }
+
+        let evictions = count(EventKind::Evict);
+        writeln!(f, "eviction age (events in pool):")?;
+        for (i, num) in self.eviction_ages.iter().enumerate().filter(|(_, num)| **num > 0) {
+            let percent = *num as f64 / evictions.max(1) as f64 * 100.0;
+            writeln!(f, "  [{}, {}): {} ({:.1}%)", 1u64 << i, 1u64 << (i + 1), num, percent)?;
+        }
+
+        writeln!(
+            f,
+            "regret: {} of {} evictions re-read within {} events ({:.1}%)",
+            self.regrets,
+            evictions,
+            self.regret_window,
+            self.regrets as f64 / evictions.max(1) as f64 * 100.0
+        )?;
+        for (page_id, num) in &self.top_regret_pages {
+            writeln!(f, "  page {}: {}", page_id, num)?;
+        }
+        writeln!(f, "dirty evictions:")?;
+        for (page_id, num) in &self.top_dirty_evictions {
+            writeln!(f, "  page {}: {}", page_id, num)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn event_recorder_test() {
+        let file = format!("./target/test_file_{:?}.events", std::thread::current().id());
+        let mut recorder = EventRecorder::create(&file).unwrap();
+        // page 1 is evicted dirty and read again right away; page 2 is not
+        let trace = [
+            (EventKind::Miss, 1, false),
+            (EventKind::Miss, 2, false),
+            (EventKind::Evict, 1, true),
+            (EventKind::Miss, 3, false),
+            (EventKind::Evict, 2, false),
+            (EventKind::Miss, 1, false),
+            (EventKind::Hit, 1, false),
+        ];
+        for (kind, page_id, dirty) in trace {
+            recorder.record(kind, page_id, 0, dirty, 0).unwrap();
+        }
+        drop(recorder);
+        // a torn last event is dropped
+        let mut log = std::fs::OpenOptions::new().append(true).open(&file).unwrap();
+        log.write_all(&[1, 2, 3]).unwrap();
+
+        let events = read_events(&file).unwrap();
+        assert_eq!(events.len(), trace.len());
+        assert_eq!(events[2].seq, 2);
+        assert_eq!((events[2].kind, events[2].page_id, events[2].dirty), (EventKind::Evict, 1, true));
+
+        let summary = summarize(&events, 4);
+        assert_eq!(summary.counts[&EventKind::Miss], 4);
+        assert_eq!(summary.regrets, 1);
+        assert_eq!(summary.top_regret_pages, vec![(1, 1)]);
+        assert_eq!(summary.top_dirty_evictions, vec![(1, 1)]);
+        // page 1 stayed for 2 events and page 2 for 3
+        assert_eq!(summary.eviction_ages, vec![0, 2]);
+        assert_eq!(summarize(&events, 2).regrets, 0);
+        assert!(summary.to_string().contains("regret: 1 of 2 evictions"));
+        let _ = std::fs::remove_file(file);
+    }
+}
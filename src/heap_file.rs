@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::buffer_manager::BMgr;
+use crate::config::*;
+use crate::page::{read_u32, read_u64, write_u32, write_u64, PAGE_HEADER_SIZE};
+use crate::slotted_page::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    Record(RecordError),
+    /// Every frame in the buffer pool is pinned.
+    BufferFull,
+}
+
+impl From<RecordError> for HeapError {
+    fn from(e: RecordError) -> Self {
+        HeapError::Record(e)
+    }
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeapError::Record(e) => write!(f, "{}", e),
+            HeapError::BufferFull => write!(f, "no free frame in buffer pool"),
+        }
+    }
+}
+
+impl std::error::Error for HeapError {}
+
+// Directory page layout (little endian), after the common page header:
+// | next_dir_page: u64 | num_entries: u32 | unused: u32 | entry 0 | entry 1 | ...
+// An entry is (page_id: u64, free_space: u32, unused: u32). Data pages are
+// slotted pages; free_space caches `SlottedPage::free_space` of each.
+const NEXT_DIR_OFFSET: usize = PAGE_HEADER_SIZE;
+const NUM_ENTRIES_OFFSET: usize = PAGE_HEADER_SIZE + 8;
+const DIR_HEADER_SIZE: usize = PAGE_HEADER_SIZE + 16;
+const DIR_ENTRY_SIZE: usize = 16;
+const NO_PAGE: u64 = u64::MAX;
+
+struct DirEntry {
+    page_id: PageId,
+    free_space: usize,
+}
+
+/// An unordered collection of records stored in slotted pages, found
+/// through a chain of directory pages starting at `get_first_page_id`.
+/// The directory is cached in memory and written through on change.
+pub struct HeapFile {
+    dir_pages: Vec<PageId>,
+    entries: Vec<DirEntry>,
+    entry_index: HashMap<PageId, usize>,
+    entries_per_dir: usize,
+}
+
+impl HeapFile {
+    pub fn create(buffer_manager: &mut BMgr) -> Result<HeapFile, HeapError> {
+        let mut page_id = 0;
+        let frame_id = buffer_manager
+            .fix_new_page(&mut page_id)
+            .ok_or(HeapError::BufferFull)?;
+        init_dir_page(buffer_manager.get_data(frame_id));
+        buffer_manager.unfix_page(page_id);
+
+        Ok(HeapFile {
+            dir_pages: vec![page_id],
+            entries: Vec::new(),
+            entry_index: HashMap::new(),
+            entries_per_dir: entries_per_dir(buffer_manager.get_page_size()),
+        })
+    }
+
+    pub fn open(buffer_manager: &mut BMgr, first_page_id: PageId) -> Result<HeapFile, HeapError> {
+        let mut heap_file = HeapFile {
+            dir_pages: Vec::new(),
+            entries: Vec::new(),
+            entry_index: HashMap::new(),
+            entries_per_dir: entries_per_dir(buffer_manager.get_page_size()),
+        };
+
+        let mut dir_page = first_page_id as u64;
+        while dir_page != NO_PAGE {
+            let page_id = dir_page as PageId;
+            let frame_id = buffer_manager
+                .fix_page(page_id, false)
+                .ok_or(HeapError::BufferFull)?;
+            let data = buffer_manager.get_data_ref(frame_id);
+            for i in 0..read_u32(data, NUM_ENTRIES_OFFSET) as usize {
+                let pos = DIR_HEADER_SIZE + i * DIR_ENTRY_SIZE;
+                let entry = DirEntry {
+                    page_id: read_u64(data, pos) as PageId,
+                    free_space: read_u32(data, pos + 8) as usize,
+                };
+                heap_file.entry_index.insert(entry.page_id, heap_file.entries.len());
+                heap_file.entries.push(entry);
+            }
+            dir_page = read_u64(data, NEXT_DIR_OFFSET);
+            buffer_manager.unfix_page(page_id);
+            heap_file.dir_pages.push(page_id);
+        }
+        Ok(heap_file)
+    }
+
+    /// The page id to pass to `open` later.
+    pub fn get_first_page_id(&self) -> PageId {
+        self.dir_pages[0]
+    }
+
+    pub fn num_data_pages(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn insert(&mut self, buffer_manager: &mut BMgr, record: &[u8]) -> Result<RecordId, HeapError> {
+        self.insert_as(buffer_manager, record, false)
+    }
+
+    /// Insert a record, as one moved behind a forwarding stub if `moved`.
+    fn insert_as(&mut self, buffer_manager: &mut BMgr, record: &[u8], moved: bool) -> Result<RecordId, HeapError> {
+        let insert = |page: &mut SlottedPage| match moved {
+            true => page.insert_moved_record(record),
+            false => page.insert_record(record),
+        };
+        if record.len() > SlottedPage::max_record_size(buffer_manager.get_page_size()) {
+            return Err(RecordError::NoSpace.into());
+        }
+        if let Some(index) = self
+            .entries
+            .iter()
+            .position(|entry| entry.free_space >= record.len())
+        {
+            let page_id = self.entries[index].page_id;
+            let frame_id = buffer_manager
+                .fix_page(page_id, true)
+                .ok_or(HeapError::BufferFull)?;
+            let mut page = SlottedPage::new(buffer_manager.get_data(frame_id));
+            let slot = insert(&mut page);
+            let free_space = page.free_space();
+            buffer_manager.unfix_page(page_id);
+            self.set_free_space(buffer_manager, index, free_space)?;
+            if let Some(slot) = slot {
+                return Ok(RecordId { page_id, slot });
+            }
+        }
+
+        let mut page_id = 0;
+        let frame_id = buffer_manager
+            .fix_new_page(&mut page_id)
+            .ok_or(HeapError::BufferFull)?;
+        let mut page = SlottedPage::init(buffer_manager.get_data(frame_id));
+        let slot = insert(&mut page).unwrap();
+        let free_space = page.free_space();
+        buffer_manager.unfix_page(page_id);
+        self.add_entry(buffer_manager, page_id, free_space)?;
+        Ok(RecordId { page_id, slot })
+    }
+
+    pub fn get(&self, buffer_manager: &mut BMgr, rid: RecordId) -> Result<Vec<u8>, HeapError> {
+        if !self.entry_index.contains_key(&rid.page_id) {
+            return Err(RecordError::NotFound.into());
+        }
+        let frame_id = buffer_manager
+            .fix_page_with_hint(rid.page_id, false, AccessType::Lookup)
+            .ok_or(HeapError::BufferFull)?;
+        let page = SlottedPage::new(buffer_manager.get_data(frame_id));
+        let found = match page.get_forward(rid.slot) {
+            Some(target) => Err(target),
+            // a moved record is only found through its stub
+            None if page.is_moved(rid.slot) => Ok(None),
+            None => Ok(page.get_record(rid.slot).map(|record| record.to_vec())),
+        };
+        buffer_manager.unfix_page(rid.page_id);
+        match found {
+            Ok(record) => record.ok_or(RecordError::NotFound.into()),
+            Err(target) => read_record(buffer_manager, target),
+        }
+    }
+
+    /// Update a record in place if its page has room. Otherwise the record
+    /// moves to another page and a forwarding stub to it takes its slot, so
+    /// its id stays valid, e.g. in an index. A moved record that grows again
+    /// moves on, and its stub is pointed at the new copy.
+    pub fn update(&mut self, buffer_manager: &mut BMgr, rid: RecordId, record: &[u8]) -> Result<(), HeapError> {
+        if record.len() > SlottedPage::max_record_size(buffer_manager.get_page_size()) {
+            return Err(RecordError::NoSpace.into());
+        }
+        let (forward, result, can_forward) = self.modify(buffer_manager, rid.page_id, |page| {
+            if page.is_moved(rid.slot) {
+                return (None, Err(RecordError::NotFound), false);
+            }
+            match page.get_forward(rid.slot) {
+                Some(target) => (Some(target), Ok(()), true),
+                None => (None, page.update_record(rid.slot, record), page.can_forward(rid.slot)),
+            }
+        })?;
+        let old_copy = match forward {
+            Some(target) => {
+                match self.modify(buffer_manager, target.page_id, |page| page.update_record(target.slot, record))? {
+                    Err(RecordError::NoSpace) => Some(target),
+                    result => return Ok(result?),
+                }
+            }
+            None => match result {
+                // too small a record on too full a page cannot leave a stub behind
+                Err(RecordError::NoSpace) if can_forward => None,
+                result => return Ok(result?),
+            },
+        };
+
+        // the stub only points at the new copy once it exists
+        let new_rid = self.insert_as(buffer_manager, record, true)?;
+        match self.modify(buffer_manager, rid.page_id, |page| page.set_forward(rid.slot, new_rid)) {
+            Ok(result) => result?,
+            Err(e) => {
+                self.delete_slot(buffer_manager, new_rid)?;
+                return Err(e);
+            }
+        }
+        match old_copy {
+            Some(old_copy) => self.delete_slot(buffer_manager, old_copy),
+            None => Ok(()),
+        }
+    }
+
+    /// Delete a record, with the copy its forwarding stub points at.
+    pub fn delete(&mut self, buffer_manager: &mut BMgr, rid: RecordId) -> Result<(), HeapError> {
+        let forward = self.modify(buffer_manager, rid.page_id, |page| {
+            if page.is_moved(rid.slot) {
+                return Err(RecordError::NotFound);
+            }
+            let forward = page.get_forward(rid.slot);
+            page.delete_record(rid.slot)?;
+            Ok(forward)
+        })??;
+        // the stub goes first, so it never points at a reused slot
+        match forward {
+            Some(target) => self.delete_slot(buffer_manager, target),
+            None => Ok(()),
+        }
+    }
+
+    fn delete_slot(&mut self, buffer_manager: &mut BMgr, rid: RecordId) -> Result<(), HeapError> {
+        Ok(self.modify(buffer_manager, rid.page_id, |page| page.delete_record(rid.slot))??)
+    }
+
+    /// Run `f` on a data page fixed for writing, then cache its free space.
+    fn modify<R>(
+        &mut self,
+        buffer_manager: &mut BMgr,
+        page_id: PageId,
+        f: impl FnOnce(&mut SlottedPage) -> R,
+    ) -> Result<R, HeapError> {
+        let index = *self.entry_index.get(&page_id).ok_or(RecordError::NotFound)?;
+        let frame_id = buffer_manager
+            .fix_page(page_id, true)
+            .ok_or(HeapError::BufferFull)?;
+        let mut page = SlottedPage::new(buffer_manager.get_data(frame_id));
+        let result = f(&mut page);
+        let free_space = page.free_space();
+        buffer_manager.unfix_page(page_id);
+        self.set_free_space(buffer_manager, index, free_space)?;
+        Ok(result)
+    }
+
+    /// Scan all records in page order, keeping only the current page fixed.
+    pub fn scan<'a>(&self, buffer_manager: &'a mut BMgr) -> HeapScan<'a> {
+        HeapScan {
+            buffer_manager,
+            page_ids: self.entries.iter().map(|entry| entry.page_id).collect(),
+            next_page: 0,
+            current: None,
+        }
+    }
+
+    fn set_free_space(
+        &mut self,
+        buffer_manager: &mut BMgr,
+        index: usize,
+        free_space: usize,
+    ) -> Result<(), HeapError> {
+        if self.entries[index].free_space == free_space {
+            return Ok(());
+        }
+        self.entries[index].free_space = free_space;
+        self.write_entry(buffer_manager, index)
+    }
+
+    fn add_entry(
+        &mut self,
+        buffer_manager: &mut BMgr,
+        page_id: PageId,
+        free_space: usize,
+    ) -> Result<(), HeapError> {
+        let index = self.entries.len();
+        if index == self.dir_pages.len() * self.entries_per_dir {
+            // chain a new directory page
+            let mut dir_page = 0;
+            let frame_id = buffer_manager
+                .fix_new_page(&mut dir_page)
+                .ok_or(HeapError::BufferFull)?;
+            init_dir_page(buffer_manager.get_data(frame_id));
+            buffer_manager.unfix_page(dir_page);
+
+            let last_dir_page = *self.dir_pages.last().unwrap();
+            let frame_id = buffer_manager
+                .fix_page(last_dir_page, true)
+                .ok_or(HeapError::BufferFull)?;
+            write_u64(buffer_manager.get_data(frame_id), NEXT_DIR_OFFSET, dir_page as u64);
+            buffer_manager.unfix_page(last_dir_page);
+            self.dir_pages.push(dir_page);
+        }
+
+        self.entries.push(DirEntry { page_id, free_space });
+        self.entry_index.insert(page_id, index);
+        self.write_entry(buffer_manager, index)
+    }
+
+    fn write_entry(&self, buffer_manager: &mut BMgr, index: usize) -> Result<(), HeapError> {
+        let dir_page = self.dir_pages[index / self.entries_per_dir];
+        let i = index % self.entries_per_dir;
+        let frame_id = buffer_manager
+            .fix_page(dir_page, true)
+            .ok_or(HeapError::BufferFull)?;
+        let data = buffer_manager.get_data(frame_id);
+        let pos = DIR_HEADER_SIZE + i * DIR_ENTRY_SIZE;
+        write_u64(data, pos, self.entries[index].page_id as u64);
+        write_u32(data, pos + 8, self.entries[index].free_space as u32);
+        if i + 1 > read_u32(data, NUM_ENTRIES_OFFSET) as usize {
+            write_u32(data, NUM_ENTRIES_OFFSET, (i + 1) as u32);
+        }
+        buffer_manager.unfix_page(dir_page);
+        Ok(())
+    }
+}
+
+fn entries_per_dir(page_size: usize) -> usize {
+    (page_size - DIR_HEADER_SIZE) / DIR_ENTRY_SIZE
+}
+
+fn init_dir_page(data: &mut Data) {
+    write_u64(data, NEXT_DIR_OFFSET, NO_PAGE);
+    write_u32(data, NUM_ENTRIES_OFFSET, 0);
+}
+
+/// Read the record in a slot directly, as the copy a stub points at.
+fn read_record(buffer_manager: &mut BMgr, rid: RecordId) -> Result<Vec<u8>, HeapError> {
+    let frame_id = buffer_manager
+        .fix_page_with_hint(rid.page_id, false, AccessType::Lookup)
+        .ok_or(HeapError::BufferFull)?;
+    let page = SlottedPage::new(buffer_manager.get_data(frame_id));
+    let record = page.get_record(rid.slot).map(|record| record.to_vec());
+    buffer_manager.unfix_page(rid.page_id);
+    record.ok_or(RecordError::NotFound.into())
+}
+
+/// A full-table scan over a `HeapFile`, created by `HeapFile::scan`. A
+/// moved record is returned under its original id, when the scan reaches
+/// its forwarding stub.
+pub struct HeapScan<'a> {
+    buffer_manager: &'a mut BMgr,
+    page_ids: Vec<PageId>,
+    next_page: usize,
+    current: Option<(PageId, FrameId, SlotId)>, // fixed page and next slot
+}
+
+impl Iterator for HeapScan<'_> {
+    type Item = Result<(RecordId, Vec<u8>), HeapError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((page_id, frame_id, slot)) = self.current {
+                let page = SlottedPage::new(self.buffer_manager.get_data(frame_id));
+                let next = (slot..page.num_slots() as SlotId).find_map(|slot| match page.get_forward(slot) {
+                    Some(target) => Some((slot, Err(target))),
+                    None if page.is_moved(slot) => None,
+                    None => Some((slot, Ok(page.get_record(slot)?.to_vec()))),
+                });
+                if let Some((slot, record)) = next {
+                    self.current = Some((page_id, frame_id, slot + 1));
+                    let record = match record {
+                        Ok(record) => Ok(record),
+                        Err(target) => read_record(self.buffer_manager, target),
+                    };
+                    return Some(record.map(|record| (RecordId { page_id, slot }, record)));
+                }
+                self.buffer_manager.unfix_page(page_id);
+                self.current = None;
+            }
+
+            let page_id = *self.page_ids.get(self.next_page)?;
+            self.next_page += 1;
+            match self
+                .buffer_manager
+                .fix_page_with_hint(page_id, false, AccessType::SequentialScan)
+            {
+                Some(frame_id) => self.current = Some((page_id, frame_id, 0)),
+                None => {
+                    self.next_page = self.page_ids.len();
+                    return Some(Err(HeapError::BufferFull));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for HeapScan<'_> {
+    fn drop(&mut self) {
+        if let Some((page_id, _, _)) = self.current {
+            self.buffer_manager.unfix_page(page_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn heap_file_test() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 4);
+        let mut heap_file = HeapFile::create(&mut buffer_manager).unwrap();
+
+        let mut rids = Vec::new();
+        for i in 0..1000 {
+            let record = format!("record {:04}", i).repeat(i % 7 + 1);
+            rids.push(heap_file.insert(&mut buffer_manager, record.as_bytes()).unwrap());
+        }
+        assert!(heap_file.num_data_pages() > 4);
+        for (i, rid) in rids.iter().enumerate() {
+            let record = format!("record {:04}", i).repeat(i % 7 + 1);
+            assert_eq!(heap_file.get(&mut buffer_manager, *rid).unwrap(), record.as_bytes());
+        }
+
+        // delete every third record, grow every fifth
+        for (i, rid) in rids.iter_mut().enumerate() {
+            if i % 3 == 0 {
+                heap_file.delete(&mut buffer_manager, *rid).unwrap();
+            } else if i % 5 == 0 {
+                // grown records move behind a stub and keep their ids
+                let record = format!("updated {:04}", i).repeat(40);
+                heap_file.update(&mut buffer_manager, *rid, record.as_bytes()).unwrap();
+                assert_eq!(heap_file.get(&mut buffer_manager, *rid).unwrap(), record.as_bytes());
+            }
+        }
+        assert_eq!(
+            heap_file.get(&mut buffer_manager, rids[0]),
+            Err(HeapError::Record(RecordError::NotFound))
+        );
+
+        let mut scanned: HashMap<RecordId, Vec<u8>> = heap_file
+            .scan(&mut buffer_manager)
+            .map(|item| item.unwrap())
+            .collect();
+        assert_eq!(scanned.len(), 1000 - 334);
+        for (i, rid) in rids.iter().enumerate().filter(|(i, _)| i % 3 != 0) {
+            let record = if i % 5 == 0 {
+                format!("updated {:04}", i).repeat(40)
+            } else {
+                format!("record {:04}", i).repeat(i % 7 + 1)
+            };
+            assert_eq!(scanned[rid], record.as_bytes());
+        }
+        // the scan unfixed every page it fixed
+        assert_eq!(buffer_manager.num_free_frames() + buffer_manager.num_unpinned_frames(), 4);
+
+        // a moved record moves on or shrinks in place behind the same stub
+        let rid = rids[5];
+        let moved = heap_file.insert_as(&mut buffer_manager, b"not a home", true).unwrap();
+        assert_eq!(
+            heap_file.get(&mut buffer_manager, moved),
+            Err(HeapError::Record(RecordError::NotFound))
+        );
+        heap_file.delete_slot(&mut buffer_manager, moved).unwrap();
+        let longer = "moved again ".repeat(300);
+        heap_file.update(&mut buffer_manager, rid, longer.as_bytes()).unwrap();
+        assert_eq!(heap_file.get(&mut buffer_manager, rid).unwrap(), longer.as_bytes());
+        heap_file.update(&mut buffer_manager, rid, b"short").unwrap();
+        assert_eq!(heap_file.get(&mut buffer_manager, rid).unwrap(), b"short");
+        assert_eq!(heap_file.scan(&mut buffer_manager).filter(|item| item.as_ref().unwrap().1 == b"short").count(), 1);
+        scanned.insert(rid, b"short".to_vec());
+
+        let too_large = vec![0; PAGE_SIZE];
+        assert_eq!(
+            heap_file.insert(&mut buffer_manager, &too_large),
+            Err(HeapError::Record(RecordError::NoSpace))
+        );
+
+        // reopen through the directory
+        let first_page_id = heap_file.get_first_page_id();
+        drop(buffer_manager);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Clock, 4);
+        let mut heap_file = HeapFile::open(&mut buffer_manager, first_page_id).unwrap();
+        let mut scan = heap_file.scan(&mut buffer_manager);
+        let (rid, record) = scan.next().unwrap().unwrap();
+        assert_eq!(record, scanned[&rid]);
+        drop(scan);
+        assert_eq!(heap_file.scan(&mut buffer_manager).count(), 1000 - 334);
+        assert_eq!(heap_file.get(&mut buffer_manager, rids[5]).unwrap(), b"short");
+        // deleting a moved record removes its stub and its copy
+        heap_file.delete(&mut buffer_manager, rids[10]).unwrap();
+        assert_eq!(
+            heap_file.get(&mut buffer_manager, rids[10]),
+            Err(HeapError::Record(RecordError::NotFound))
+        );
+        assert_eq!(heap_file.scan(&mut buffer_manager).count(), 1000 - 335);
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+}
@@ -0,0 +1,672 @@
+use std::ops::{Bound, RangeBounds};
+
+use crate::buffer_manager::BMgr;
+use crate::config::*;
+use crate::index::IndexError;
+use crate::page::{read_u16, read_u64, write_u16, write_u64, PAGE_HEADER_SIZE};
+use crate::slotted_page::RecordId;
+
+// Layouts (little endian), after the common page header:
+// Meta page: | root: u64 |
+// Node page:
+// | node_type: u8 | unused: u8 | num_keys: u16 | unused: u32 | next_leaf: u64 | entries ...
+// A leaf entry is (key_len: u16, key, page_id: u64, slot: u16). An internal
+// node stores child 0 (u64) first, then (key_len: u16, key, child: u64) per key.
+const ROOT_OFFSET: usize = PAGE_HEADER_SIZE;
+const NODE_TYPE_OFFSET: usize = PAGE_HEADER_SIZE;
+const NUM_KEYS_OFFSET: usize = PAGE_HEADER_SIZE + 2;
+const NEXT_LEAF_OFFSET: usize = PAGE_HEADER_SIZE + 8;
+const NODE_HEADER_SIZE: usize = PAGE_HEADER_SIZE + 16;
+const LEAF: u8 = 1;
+const INTERNAL: u8 = 2;
+const NO_PAGE: u64 = u64::MAX;
+
+/// Encode a u64 key so that byte order matches numeric order.
+pub fn key_from_u64(key: u64) -> [u8; 8] {
+    key.to_be_bytes()
+}
+
+struct Leaf {
+    keys: Vec<Vec<u8>>,
+    values: Vec<RecordId>,
+    next: Option<PageId>,
+}
+
+struct Internal {
+    keys: Vec<Vec<u8>>,
+    children: Vec<PageId>,
+}
+
+enum Node {
+    Leaf(Leaf),
+    Internal(Internal),
+}
+
+impl Leaf {
+    fn entry_size(key: &[u8]) -> usize {
+        2 + key.len() + 10
+    }
+
+    fn size(&self) -> usize {
+        NODE_HEADER_SIZE + self.keys.iter().map(|key| Self::entry_size(key)).sum::<usize>()
+    }
+
+    /// Split off the upper half (by bytes) into a new leaf.
+    fn split(&mut self) -> Leaf {
+        let mid = split_point(self.keys.iter().map(|key| Self::entry_size(key)));
+        Leaf {
+            keys: self.keys.split_off(mid),
+            values: self.values.split_off(mid),
+            next: self.next,
+        }
+    }
+}
+
+impl Internal {
+    fn entry_size(key: &[u8]) -> usize {
+        2 + key.len() + 8
+    }
+
+    fn size(&self) -> usize {
+        NODE_HEADER_SIZE + 8 + self.keys.iter().map(|key| Self::entry_size(key)).sum::<usize>()
+    }
+
+    fn child_index(&self, key: &[u8]) -> usize {
+        self.keys.partition_point(|k| k.as_slice() <= key)
+    }
+
+    /// Split off the upper half (by bytes) into a new node, returning the
+    /// separator key that moves up to the parent.
+    fn split(&mut self) -> (Vec<u8>, Internal) {
+        // an overflowing node has at least three keys, leave one on each side
+        let mid = split_point(self.keys.iter().map(|key| Self::entry_size(key)));
+        let mid = mid.min(self.keys.len() - 2);
+        let right = Internal {
+            keys: self.keys.split_off(mid + 1),
+            children: self.children.split_off(mid + 1),
+        };
+        (self.keys.pop().unwrap(), right)
+    }
+}
+
+impl Node {
+    fn size(&self) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.size(),
+            Node::Internal(internal) => internal.size(),
+        }
+    }
+
+    fn num_keys(&self) -> usize {
+        match self {
+            Node::Leaf(leaf) => leaf.keys.len(),
+            Node::Internal(internal) => internal.keys.len(),
+        }
+    }
+
+    fn read(data: &Data) -> Node {
+        let num_keys = read_u16(data, NUM_KEYS_OFFSET) as usize;
+        let mut pos = NODE_HEADER_SIZE;
+        let mut keys = Vec::with_capacity(num_keys);
+        let mut read_key = |pos: &mut usize| {
+            let len = read_u16(data, *pos) as usize;
+            keys.push(data[*pos + 2..*pos + 2 + len].to_vec());
+            *pos += 2 + len;
+        };
+
+        if data[NODE_TYPE_OFFSET] == LEAF {
+            let mut values = Vec::with_capacity(num_keys);
+            for _ in 0..num_keys {
+                read_key(&mut pos);
+                values.push(RecordId {
+                    page_id: read_u64(data, pos) as PageId,
+                    slot: read_u16(data, pos + 8),
+                });
+                pos += 10;
+            }
+            let next = read_u64(data, NEXT_LEAF_OFFSET);
+            Node::Leaf(Leaf {
+                keys,
+                values,
+                next: (next != NO_PAGE).then_some(next as PageId),
+            })
+        } else {
+            let mut children = Vec::with_capacity(num_keys + 1);
+            children.push(read_u64(data, pos) as PageId);
+            pos += 8;
+            for _ in 0..num_keys {
+                read_key(&mut pos);
+                children.push(read_u64(data, pos) as PageId);
+                pos += 8;
+            }
+            Node::Internal(Internal { keys, children })
+        }
+    }
+
+    fn write(&self, data: &mut Data) {
+        write_u16(data, NUM_KEYS_OFFSET, self.num_keys() as u16);
+        let mut pos = NODE_HEADER_SIZE;
+        let write_key = |data: &mut Data, pos: &mut usize, key: &[u8]| {
+            write_u16(data, *pos, key.len() as u16);
+            data[*pos + 2..*pos + 2 + key.len()].copy_from_slice(key);
+            *pos += 2 + key.len();
+        };
+
+        match self {
+            Node::Leaf(leaf) => {
+                data[NODE_TYPE_OFFSET] = LEAF;
+                write_u64(data, NEXT_LEAF_OFFSET, leaf.next.map_or(NO_PAGE, |next| next as u64));
+                for (key, value) in leaf.keys.iter().zip(&leaf.values) {
+                    write_key(data, &mut pos, key);
+                    write_u64(data, pos, value.page_id as u64);
+                    write_u16(data, pos + 8, value.slot);
+                    pos += 10;
+                }
+            }
+            Node::Internal(internal) => {
+                data[NODE_TYPE_OFFSET] = INTERNAL;
+                write_u64(data, NEXT_LEAF_OFFSET, NO_PAGE);
+                write_u64(data, pos, internal.children[0] as u64);
+                pos += 8;
+                for (key, child) in internal.keys.iter().zip(&internal.children[1..]) {
+                    write_key(data, &mut pos, key);
+                    write_u64(data, pos, *child as u64);
+                    pos += 8;
+                }
+            }
+        }
+    }
+}
+
+/// The index at which to split entries of the given sizes into two halves
+/// of about the same number of bytes, keeping at least one entry per side.
+fn split_point(sizes: impl Iterator<Item = usize>) -> usize {
+    let sizes: Vec<usize> = sizes.collect();
+    let total: usize = sizes.iter().sum();
+    let mut left = 0;
+    for (i, size) in sizes.iter().enumerate() {
+        if left + size > total / 2 {
+            return i.clamp(1, sizes.len() - 1);
+        }
+        left += size;
+    }
+    sizes.len() - 1
+}
+
+/// A B+ tree mapping unique byte-string keys to record ids, with nodes
+/// stored in pages fetched through `BMgr`. u64 keys are encoded with
+/// `key_from_u64`.
+///
+/// A tree requires exclusive `&mut BMgr`: every operation fixes one node at
+/// a time and unfixes it before moving on, without latching pages, so it
+/// cannot run over a shared pool such as `ShardedBMgr`. Latch crabbing for
+/// concurrent operations is not implemented yet. Node pages are fixed with
+/// `AccessType::Index`.
+pub struct BPlusTree {
+    meta_page_id: PageId,
+    root: PageId,
+    page_size: usize,
+}
+
+impl BPlusTree {
+    pub fn create(buffer_manager: &mut BMgr) -> Result<BPlusTree, IndexError> {
+        let mut meta_page_id = 0;
+        buffer_manager
+            .fix_new_page(&mut meta_page_id)
+            .ok_or(IndexError::BufferFull)?;
+        buffer_manager.unfix_page(meta_page_id);
+
+        let mut tree = BPlusTree {
+            meta_page_id,
+            root: 0,
+            page_size: buffer_manager.get_page_size(),
+        };
+        let root = Node::Leaf(Leaf {
+            keys: Vec::new(),
+            values: Vec::new(),
+            next: None,
+        });
+        tree.root = tree.new_node(buffer_manager, &root)?;
+        tree.write_meta(buffer_manager)?;
+        Ok(tree)
+    }
+
+    pub fn open(buffer_manager: &mut BMgr, meta_page_id: PageId) -> Result<BPlusTree, IndexError> {
+        let frame_id = buffer_manager
+            .fix_page_with_hint(meta_page_id, false, AccessType::Index)
+            .ok_or(IndexError::BufferFull)?;
+        let root = read_u64(buffer_manager.get_data_ref(frame_id), ROOT_OFFSET) as PageId;
+        buffer_manager.unfix_page(meta_page_id);
+        Ok(BPlusTree {
+            meta_page_id,
+            root,
+            page_size: buffer_manager.get_page_size(),
+        })
+    }
+
+    /// The page id to pass to `open` later.
+    pub fn get_meta_page_id(&self) -> PageId {
+        self.meta_page_id
+    }
+
+    /// Keys must leave room for a few entries per node.
+    pub fn max_key_size(&self) -> usize {
+        self.page_size / 4 - NODE_HEADER_SIZE - Leaf::entry_size(&[])
+    }
+
+    pub fn get(&self, buffer_manager: &mut BMgr, key: &[u8]) -> Result<Option<RecordId>, IndexError> {
+        let (_, leaf) = self.find_leaf(buffer_manager, Bound::Included(key))?;
+        Ok(leaf
+            .keys
+            .binary_search_by(|k| k.as_slice().cmp(key))
+            .ok()
+            .map(|i| leaf.values[i]))
+    }
+
+    /// Insert or replace the value of `key`, returning the old value.
+    pub fn insert(
+        &mut self,
+        buffer_manager: &mut BMgr,
+        key: &[u8],
+        value: RecordId,
+    ) -> Result<Option<RecordId>, IndexError> {
+        if key.len() > self.max_key_size() {
+            return Err(IndexError::KeyTooLarge);
+        }
+
+        let (old, split) = self.insert_into(buffer_manager, self.root, key, value)?;
+        if let Some((separator, right)) = split {
+            let root = Node::Internal(Internal {
+                keys: vec![separator],
+                children: vec![self.root, right],
+            });
+            self.root = self.new_node(buffer_manager, &root)?;
+            self.write_meta(buffer_manager)?;
+        }
+        Ok(old)
+    }
+
+    /// Remove `key`, returning its value.
+    pub fn remove(&mut self, buffer_manager: &mut BMgr, key: &[u8]) -> Result<Option<RecordId>, IndexError> {
+        let removed = self.remove_from(buffer_manager, self.root, key)?;
+        if let Node::Internal(root) = self.read_node(buffer_manager, self.root)? {
+            if root.keys.is_empty() {
+                self.root = root.children[0];
+                self.write_meta(buffer_manager)?;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Iterate over the entries with keys in `range`, in key order.
+    pub fn range<'a, R: RangeBounds<[u8]>>(
+        &self,
+        buffer_manager: &'a mut BMgr,
+        range: R,
+    ) -> Result<RangeScan<'a>, IndexError> {
+        let start = range.start_bound().map(|key| key.to_vec());
+        let end = range.end_bound().map(|key| key.to_vec());
+        let (_, leaf) = self.find_leaf(buffer_manager, start.as_ref().map(|key| key.as_slice()))?;
+        let pos = match &start {
+            Bound::Included(key) => leaf.keys.partition_point(|k| k < key),
+            Bound::Excluded(key) => leaf.keys.partition_point(|k| k <= key),
+            Bound::Unbounded => 0,
+        };
+        Ok(RangeScan {
+            buffer_manager,
+            leaf,
+            pos,
+            end,
+        })
+    }
+
+    fn find_leaf(&self, buffer_manager: &mut BMgr, key: Bound<&[u8]>) -> Result<(PageId, Leaf), IndexError> {
+        let mut page_id = self.root;
+        loop {
+            match self.read_node(buffer_manager, page_id)? {
+                Node::Leaf(leaf) => return Ok((page_id, leaf)),
+                Node::Internal(internal) => {
+                    page_id = match key {
+                        Bound::Included(key) | Bound::Excluded(key) => {
+                            internal.children[internal.child_index(key)]
+                        }
+                        Bound::Unbounded => internal.children[0],
+                    }
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn insert_into(
+        &mut self,
+        buffer_manager: &mut BMgr,
+        page_id: PageId,
+        key: &[u8],
+        value: RecordId,
+    ) -> Result<(Option<RecordId>, Option<(Vec<u8>, PageId)>), IndexError> {
+        match self.read_node(buffer_manager, page_id)? {
+            Node::Leaf(mut leaf) => {
+                match leaf.keys.binary_search_by(|k| k.as_slice().cmp(key)) {
+                    Ok(i) => {
+                        let old = std::mem::replace(&mut leaf.values[i], value);
+                        self.write_node(buffer_manager, page_id, &Node::Leaf(leaf))?;
+                        return Ok((Some(old), None));
+                    }
+                    Err(i) => {
+                        leaf.keys.insert(i, key.to_vec());
+                        leaf.values.insert(i, value);
+                    }
+                }
+                if leaf.size() <= self.page_size {
+                    self.write_node(buffer_manager, page_id, &Node::Leaf(leaf))?;
+                    return Ok((None, None));
+                }
+
+                let mut right = leaf.split();
+                let separator = right.keys[0].clone();
+                right.next = leaf.next;
+                let right_page_id = self.new_node(buffer_manager, &Node::Leaf(right))?;
+                leaf.next = Some(right_page_id);
+                self.write_node(buffer_manager, page_id, &Node::Leaf(leaf))?;
+                Ok((None, Some((separator, right_page_id))))
+            }
+            Node::Internal(mut internal) => {
+                let i = internal.child_index(key);
+                let (old, split) = self.insert_into(buffer_manager, internal.children[i], key, value)?;
+                let Some((separator, child)) = split else {
+                    return Ok((old, None));
+                };
+
+                internal.keys.insert(i, separator);
+                internal.children.insert(i + 1, child);
+                if internal.size() <= self.page_size {
+                    self.write_node(buffer_manager, page_id, &Node::Internal(internal))?;
+                    return Ok((old, None));
+                }
+
+                let (separator, right) = internal.split();
+                let right_page_id = self.new_node(buffer_manager, &Node::Internal(right))?;
+                self.write_node(buffer_manager, page_id, &Node::Internal(internal))?;
+                Ok((old, Some((separator, right_page_id))))
+            }
+        }
+    }
+
+    fn remove_from(
+        &mut self,
+        buffer_manager: &mut BMgr,
+        page_id: PageId,
+        key: &[u8],
+    ) -> Result<Option<RecordId>, IndexError> {
+        match self.read_node(buffer_manager, page_id)? {
+            Node::Leaf(mut leaf) => match leaf.keys.binary_search_by(|k| k.as_slice().cmp(key)) {
+                Ok(i) => {
+                    leaf.keys.remove(i);
+                    let removed = leaf.values.remove(i);
+                    self.write_node(buffer_manager, page_id, &Node::Leaf(leaf))?;
+                    Ok(Some(removed))
+                }
+                Err(_) => Ok(None),
+            },
+            Node::Internal(mut internal) => {
+                let i = internal.child_index(key);
+                let removed = self.remove_from(buffer_manager, internal.children[i], key)?;
+                if removed.is_some() {
+                    let child = self.read_node(buffer_manager, internal.children[i])?;
+                    if self.is_underfull(&child) {
+                        // fix up with the left sibling, or the right one for child 0
+                        let left = if i > 0 { i - 1 } else { 0 };
+                        self.rebalance(buffer_manager, &mut internal, left)?;
+                        self.write_node(buffer_manager, page_id, &Node::Internal(internal))?;
+                    }
+                }
+                Ok(removed)
+            }
+        }
+    }
+
+    fn is_underfull(&self, node: &Node) -> bool {
+        node.num_keys() == 0 || node.size() < self.page_size / 4
+    }
+
+    /// Merge children `left` and `left + 1` of `parent` if they fit in one
+    /// page, otherwise redistribute their entries evenly.
+    fn rebalance(&mut self, buffer_manager: &mut BMgr, parent: &mut Internal, left: usize) -> Result<(), IndexError> {
+        let left_page_id = parent.children[left];
+        let right_page_id = parent.children[left + 1];
+        let left_node = self.read_node(buffer_manager, left_page_id)?;
+        let right_node = self.read_node(buffer_manager, right_page_id)?;
+
+        match (left_node, right_node) {
+            (Node::Leaf(mut left_leaf), Node::Leaf(right_leaf)) => {
+                left_leaf.keys.extend(right_leaf.keys);
+                left_leaf.values.extend(right_leaf.values);
+                left_leaf.next = right_leaf.next;
+                if left_leaf.size() <= self.page_size {
+                    parent.keys.remove(left);
+                    parent.children.remove(left + 1);
+                    self.write_node(buffer_manager, left_page_id, &Node::Leaf(left_leaf))?;
+                } else {
+                    let right_leaf = left_leaf.split();
+                    left_leaf.next = Some(right_page_id);
+                    parent.keys[left] = right_leaf.keys[0].clone();
+                    self.write_node(buffer_manager, left_page_id, &Node::Leaf(left_leaf))?;
+                    self.write_node(buffer_manager, right_page_id, &Node::Leaf(right_leaf))?;
+                }
+            }
+            (Node::Internal(mut left_node), Node::Internal(right_node)) => {
+                left_node.keys.push(parent.keys[left].clone());
+                left_node.keys.extend(right_node.keys);
+                left_node.children.extend(right_node.children);
+                if left_node.size() <= self.page_size {
+                    parent.keys.remove(left);
+                    parent.children.remove(left + 1);
+                    self.write_node(buffer_manager, left_page_id, &Node::Internal(left_node))?;
+                } else {
+                    let (separator, right_node) = left_node.split();
+                    parent.keys[left] = separator;
+                    self.write_node(buffer_manager, left_page_id, &Node::Internal(left_node))?;
+                    self.write_node(buffer_manager, right_page_id, &Node::Internal(right_node))?;
+                }
+            }
+            _ => unreachable!("siblings are at the same level"),
+        }
+        Ok(())
+    }
+
+    fn read_node(&self, buffer_manager: &mut BMgr, page_id: PageId) -> Result<Node, IndexError> {
+        let frame_id = buffer_manager
+            .fix_page_with_hint(page_id, false, AccessType::Index)
+            .ok_or(IndexError::BufferFull)?;
+        let node = Node::read(buffer_manager.get_data_ref(frame_id));
+        buffer_manager.unfix_page(page_id);
+        Ok(node)
+    }
+
+    fn write_node(&self, buffer_manager: &mut BMgr, page_id: PageId, node: &Node) -> Result<(), IndexError> {
+        let frame_id = buffer_manager
+            .fix_page_with_hint(page_id, true, AccessType::Index)
+            .ok_or(IndexError::BufferFull)?;
+        node.write(buffer_manager.get_data(frame_id));
+        buffer_manager.unfix_page(page_id);
+        Ok(())
+    }
+
+    fn new_node(&self, buffer_manager: &mut BMgr, node: &Node) -> Result<PageId, IndexError> {
+        let mut page_id = 0;
+        let frame_id = buffer_manager
+            .fix_new_page(&mut page_id)
+            .ok_or(IndexError::BufferFull)?;
+        node.write(buffer_manager.get_data(frame_id));
+        buffer_manager.unfix_page(page_id);
+        Ok(page_id)
+    }
+
+    fn write_meta(&self, buffer_manager: &mut BMgr) -> Result<(), IndexError> {
+        let frame_id = buffer_manager
+            .fix_page_with_hint(self.meta_page_id, true, AccessType::Index)
+            .ok_or(IndexError::BufferFull)?;
+        write_u64(buffer_manager.get_data(frame_id), ROOT_OFFSET, self.root as u64);
+        buffer_manager.unfix_page(self.meta_page_id);
+        Ok(())
+    }
+}
+
+/// An iterator over a key range of a `BPlusTree`, created by `range`. It
+/// holds a copy of the current leaf and fixes the next leaf when it runs out.
+pub struct RangeScan<'a> {
+    buffer_manager: &'a mut BMgr,
+    leaf: Leaf,
+    pos: usize,
+    end: Bound<Vec<u8>>,
+}
+
+impl Iterator for RangeScan<'_> {
+    type Item = Result<(Vec<u8>, RecordId), IndexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos == self.leaf.keys.len() {
+            let next = self.leaf.next?;
+            let frame_id = match self.buffer_manager.fix_page_with_hint(next, false, AccessType::Index) {
+                Some(frame_id) => frame_id,
+                None => {
+                    self.leaf.next = None;
+                    return Some(Err(IndexError::BufferFull));
+                }
+            };
+            let node = Node::read(self.buffer_manager.get_data_ref(frame_id));
+            self.buffer_manager.unfix_page(next);
+            let Node::Leaf(leaf) = node else {
+                unreachable!("leaves only link to leaves")
+            };
+            self.leaf = leaf;
+            self.pos = 0;
+        }
+
+        let key = &self.leaf.keys[self.pos];
+        let in_range = match &self.end {
+            Bound::Included(end) => key <= end,
+            Bound::Excluded(end) => key < end,
+            Bound::Unbounded => true,
+        };
+        if !in_range {
+            self.leaf.keys.truncate(self.pos);
+            self.leaf.next = None;
+            return None;
+        }
+        self.pos += 1;
+        Some(Ok((key.clone(), self.leaf.values[self.pos - 1])))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rid(i: u64) -> RecordId {
+        RecordId {
+            page_id: i as PageId,
+            slot: (i % 100) as u16,
+        }
+    }
+
+    #[test]
+    fn b_plus_tree_u64_test() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 8);
+        let mut tree = BPlusTree::create(&mut buffer_manager).unwrap();
+
+        let n = 5000;
+        // a permutation of 0..n
+        let keys: Vec<u64> = (0..n).map(|i| i * 7919 % n).collect();
+        for &k in &keys {
+            assert_eq!(tree.insert(&mut buffer_manager, &key_from_u64(k), rid(k)).unwrap(), None);
+        }
+        for k in 0..n {
+            assert_eq!(tree.get(&mut buffer_manager, &key_from_u64(k)).unwrap(), Some(rid(k)));
+        }
+        assert_eq!(tree.get(&mut buffer_manager, &key_from_u64(n)).unwrap(), None);
+        assert_eq!(
+            tree.insert(&mut buffer_manager, &key_from_u64(7), rid(8)).unwrap(),
+            Some(rid(7))
+        );
+        tree.insert(&mut buffer_manager, &key_from_u64(7), rid(7)).unwrap();
+
+        let (start, end) = (key_from_u64(1000), key_from_u64(2000));
+        let scanned: Vec<u64> = tree
+            .range(&mut buffer_manager, (Bound::Included(&start[..]), Bound::Excluded(&end[..])))
+            .unwrap()
+            .map(|item| u64::from_be_bytes(item.unwrap().0.try_into().unwrap()))
+            .collect();
+        assert_eq!(scanned, (1000..2000).collect::<Vec<u64>>());
+        assert_eq!(tree.range(&mut buffer_manager, ..).unwrap().count(), n as usize);
+        let bounds = (Bound::Excluded(&start[..]), Bound::Included(&end[..]));
+        assert_eq!(tree.range(&mut buffer_manager, bounds).unwrap().count(), 1000);
+
+        // delete all odd keys, then everything
+        for &k in keys.iter().filter(|&&k| k % 2 == 1) {
+            assert_eq!(tree.remove(&mut buffer_manager, &key_from_u64(k)).unwrap(), Some(rid(k)));
+        }
+        assert_eq!(tree.remove(&mut buffer_manager, &key_from_u64(1)).unwrap(), None);
+        let scanned: Vec<u64> = tree
+            .range(&mut buffer_manager, ..)
+            .unwrap()
+            .map(|item| u64::from_be_bytes(item.unwrap().0.try_into().unwrap()))
+            .collect();
+        assert_eq!(scanned, (0..n).step_by(2).collect::<Vec<u64>>());
+
+        // reopen through the meta page
+        let meta_page_id = tree.get_meta_page_id();
+        drop(buffer_manager);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Clock, 8);
+        let mut tree = BPlusTree::open(&mut buffer_manager, meta_page_id).unwrap();
+        assert_eq!(tree.get(&mut buffer_manager, &key_from_u64(4998)).unwrap(), Some(rid(4998)));
+        for k in (0..n).step_by(2) {
+            assert_eq!(tree.remove(&mut buffer_manager, &key_from_u64(k)).unwrap(), Some(rid(k)));
+        }
+        assert_eq!(tree.range(&mut buffer_manager, ..).unwrap().count(), 0);
+        assert!(matches!(tree.read_node(&mut buffer_manager, tree.root).unwrap(), Node::Leaf(_)));
+        assert_eq!(buffer_manager.num_free_frames() + buffer_manager.num_unpinned_frames(), 8);
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn b_plus_tree_bytes_test() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Clock, 8);
+        let mut tree = BPlusTree::create(&mut buffer_manager).unwrap();
+
+        // variable-length keys, some of them close to the size limit
+        let key = |i: u64| format!("{:05}", i).repeat(1 + (i as usize * 31) % 190).into_bytes();
+        assert!(key(6).len() <= tree.max_key_size());
+        for i in 0..2000 {
+            tree.insert(&mut buffer_manager, &key(i), rid(i)).unwrap();
+        }
+        let too_long = vec![b'x'; tree.max_key_size() + 1];
+        assert_eq!(
+            tree.insert(&mut buffer_manager, &too_long, rid(0)),
+            Err(IndexError::KeyTooLarge)
+        );
+
+        let mut expect: Vec<Vec<u8>> = (0..2000).map(key).collect();
+        expect.sort();
+        let scanned: Vec<Vec<u8>> = tree
+            .range(&mut buffer_manager, ..)
+            .unwrap()
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(scanned, expect);
+
+        for i in (0..2000).filter(|i| i % 3 != 0) {
+            assert_eq!(tree.remove(&mut buffer_manager, &key(i)).unwrap(), Some(rid(i)));
+        }
+        for i in 0..2000 {
+            let expect = (i % 3 == 0).then_some(rid(i));
+            assert_eq!(tree.get(&mut buffer_manager, &key(i)).unwrap(), expect);
+        }
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+}
@@ -0,0 +1,461 @@
+use crate::buffer_manager::BMgr;
+use crate::config::*;
+use crate::index::IndexError;
+use crate::page::{read_u16, read_u32, read_u64, write_u16, write_u32, write_u64, PAGE_HEADER_SIZE};
+use crate::slotted_page::RecordId;
+
+// Layouts (little endian), after the common page header:
+// Directory page: | global_depth: u32 | unused: u32 | bucket page id: u64 * 2^global_depth |
+// Bucket page:
+// | local_depth: u32 | num_entries: u32 | entries ... |
+// An entry is (key_len: u16, key, page_id: u64, slot: u16).
+const GLOBAL_DEPTH_OFFSET: usize = PAGE_HEADER_SIZE;
+const DIR_HEADER_SIZE: usize = PAGE_HEADER_SIZE + 8;
+const LOCAL_DEPTH_OFFSET: usize = PAGE_HEADER_SIZE;
+const NUM_ENTRIES_OFFSET: usize = PAGE_HEADER_SIZE + 4;
+const BUCKET_HEADER_SIZE: usize = PAGE_HEADER_SIZE + 8;
+
+/// 64-bit FNV-1a with a murmur3 finalizer so the low bits, which index the
+/// directory, are well mixed. Stable across runs and platforms.
+fn hash(key: &[u8]) -> u64 {
+    let mut h = key
+        .iter()
+        .fold(0xcbf29ce484222325, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^ (h >> 33)
+}
+
+struct Bucket {
+    local_depth: u32,
+    keys: Vec<Vec<u8>>,
+    values: Vec<RecordId>,
+}
+
+impl Bucket {
+    fn entry_size(key: &[u8]) -> usize {
+        2 + key.len() + 10
+    }
+
+    fn size(&self) -> usize {
+        BUCKET_HEADER_SIZE + self.keys.iter().map(|key| Self::entry_size(key)).sum::<usize>()
+    }
+
+    fn position(&self, key: &[u8]) -> Option<usize> {
+        self.keys.iter().position(|k| k == key)
+    }
+
+    fn read(data: &Data) -> Bucket {
+        let num_entries = read_u32(data, NUM_ENTRIES_OFFSET) as usize;
+        let mut bucket = Bucket {
+            local_depth: read_u32(data, LOCAL_DEPTH_OFFSET),
+            keys: Vec::with_capacity(num_entries),
+            values: Vec::with_capacity(num_entries),
+        };
+        let mut pos = BUCKET_HEADER_SIZE;
+        for _ in 0..num_entries {
+            let len = read_u16(data, pos) as usize;
+            bucket.keys.push(data[pos + 2..pos + 2 + len].to_vec());
+            pos += 2 + len;
+            bucket.values.push(RecordId {
+                page_id: read_u64(data, pos) as PageId,
+                slot: read_u16(data, pos + 8),
+            });
+            pos += 10;
+        }
+        bucket
+    }
+
+    fn write(&self, data: &mut Data) {
+        write_u32(data, LOCAL_DEPTH_OFFSET, self.local_depth);
+        write_u32(data, NUM_ENTRIES_OFFSET, self.keys.len() as u32);
+        let mut pos = BUCKET_HEADER_SIZE;
+        for (key, value) in self.keys.iter().zip(&self.values) {
+            write_u16(data, pos, key.len() as u16);
+            data[pos + 2..pos + 2 + key.len()].copy_from_slice(key);
+            pos += 2 + key.len();
+            write_u64(data, pos, value.page_id as u64);
+            write_u16(data, pos + 8, value.slot);
+            pos += 10;
+        }
+    }
+}
+
+struct Directory {
+    global_depth: u32,
+    buckets: Vec<PageId>,
+}
+
+impl Directory {
+    fn index(&self, key: &[u8]) -> usize {
+        (hash(key) & ((1 << self.global_depth) - 1)) as usize
+    }
+}
+
+/// An extendible hash table mapping unique byte-string keys to record ids.
+/// One directory page points to bucket pages; a full bucket is split, and
+/// the directory doubled when the bucket is at global depth. Buckets merge
+/// with their split image on delete once both fit in half a page.
+pub struct ExtendibleHash {
+    dir_page_id: PageId,
+    page_size: usize,
+}
+
+impl ExtendibleHash {
+    pub fn create(buffer_manager: &mut BMgr) -> Result<ExtendibleHash, IndexError> {
+        let mut dir_page_id = 0;
+        buffer_manager
+            .fix_new_page(&mut dir_page_id)
+            .ok_or(IndexError::BufferFull)?;
+        buffer_manager.unfix_page(dir_page_id);
+
+        let hash_index = ExtendibleHash {
+            dir_page_id,
+            page_size: buffer_manager.get_page_size(),
+        };
+        let bucket = Bucket {
+            local_depth: 0,
+            keys: Vec::new(),
+            values: Vec::new(),
+        };
+        let bucket_page_id = hash_index.new_bucket(buffer_manager, &bucket)?;
+        let directory = Directory {
+            global_depth: 0,
+            buckets: vec![bucket_page_id],
+        };
+        hash_index.write_directory(buffer_manager, &directory)?;
+        Ok(hash_index)
+    }
+
+    pub fn open(buffer_manager: &mut BMgr, dir_page_id: PageId) -> ExtendibleHash {
+        ExtendibleHash {
+            dir_page_id,
+            page_size: buffer_manager.get_page_size(),
+        }
+    }
+
+    /// The page id to pass to `open` later.
+    pub fn get_dir_page_id(&self) -> PageId {
+        self.dir_page_id
+    }
+
+    /// Keys must leave room for a few entries per bucket.
+    pub fn max_key_size(&self) -> usize {
+        self.page_size / 4 - BUCKET_HEADER_SIZE - Bucket::entry_size(&[])
+    }
+
+    pub fn max_global_depth(&self) -> u32 {
+        ((self.page_size - DIR_HEADER_SIZE) / 8).ilog2()
+    }
+
+    pub fn get_global_depth(&self, buffer_manager: &mut BMgr) -> Result<u32, IndexError> {
+        Ok(self.read_directory(buffer_manager)?.global_depth)
+    }
+
+    pub fn get(&self, buffer_manager: &mut BMgr, key: &[u8]) -> Result<Option<RecordId>, IndexError> {
+        let directory = self.read_directory(buffer_manager)?;
+        let bucket = self.read_bucket(buffer_manager, directory.buckets[directory.index(key)])?;
+        Ok(bucket.position(key).map(|i| bucket.values[i]))
+    }
+
+    /// Insert or replace the value of `key`, returning the old value.
+    pub fn insert(
+        &mut self,
+        buffer_manager: &mut BMgr,
+        key: &[u8],
+        value: RecordId,
+    ) -> Result<Option<RecordId>, IndexError> {
+        if key.len() > self.max_key_size() {
+            return Err(IndexError::KeyTooLarge);
+        }
+
+        let mut directory = self.read_directory(buffer_manager)?;
+        loop {
+            let index = directory.index(key);
+            let bucket_page_id = directory.buckets[index];
+            let mut bucket = self.read_bucket(buffer_manager, bucket_page_id)?;
+            if let Some(i) = bucket.position(key) {
+                let old = std::mem::replace(&mut bucket.values[i], value);
+                self.write_bucket(buffer_manager, bucket_page_id, &bucket)?;
+                return Ok(Some(old));
+            }
+            if bucket.size() + Bucket::entry_size(key) <= self.page_size {
+                bucket.keys.push(key.to_vec());
+                bucket.values.push(value);
+                self.write_bucket(buffer_manager, bucket_page_id, &bucket)?;
+                return Ok(None);
+            }
+
+            if bucket.local_depth == directory.global_depth {
+                if directory.global_depth == self.max_global_depth() {
+                    return Err(IndexError::DirectoryFull);
+                }
+                directory.buckets.extend_from_within(..);
+                directory.global_depth += 1;
+            }
+            self.split_bucket(buffer_manager, &mut directory, index, bucket)?;
+            self.write_directory(buffer_manager, &directory)?;
+        }
+    }
+
+    /// Remove `key`, returning its value.
+    pub fn remove(&mut self, buffer_manager: &mut BMgr, key: &[u8]) -> Result<Option<RecordId>, IndexError> {
+        let mut directory = self.read_directory(buffer_manager)?;
+        let index = directory.index(key);
+        let bucket_page_id = directory.buckets[index];
+        let mut bucket = self.read_bucket(buffer_manager, bucket_page_id)?;
+        let Some(i) = bucket.position(key) else {
+            return Ok(None);
+        };
+        bucket.keys.remove(i);
+        let removed = bucket.values.remove(i);
+        self.write_bucket(buffer_manager, bucket_page_id, &bucket)?;
+
+        if self.merge_buckets(buffer_manager, &mut directory, index, bucket)? {
+            while directory.global_depth > 0 && self.can_shrink(buffer_manager, &directory)? {
+                directory.global_depth -= 1;
+                directory.buckets.truncate(1 << directory.global_depth);
+            }
+            self.write_directory(buffer_manager, &directory)?;
+        }
+        Ok(Some(removed))
+    }
+
+    /// Split the bucket at directory `index` on its next hash bit.
+    fn split_bucket(
+        &self,
+        buffer_manager: &mut BMgr,
+        directory: &mut Directory,
+        index: usize,
+        bucket: Bucket,
+    ) -> Result<(), IndexError> {
+        let bucket_page_id = directory.buckets[index];
+        let bit = 1 << bucket.local_depth;
+        let mut low = Bucket {
+            local_depth: bucket.local_depth + 1,
+            keys: Vec::new(),
+            values: Vec::new(),
+        };
+        let mut high = Bucket {
+            local_depth: bucket.local_depth + 1,
+            keys: Vec::new(),
+            values: Vec::new(),
+        };
+        for (key, value) in bucket.keys.into_iter().zip(bucket.values) {
+            let target = if hash(&key) & bit == 0 { &mut low } else { &mut high };
+            target.keys.push(key);
+            target.values.push(value);
+        }
+
+        let high_page_id = self.new_bucket(buffer_manager, &high)?;
+        self.write_bucket(buffer_manager, bucket_page_id, &low)?;
+        for (i, page_id) in directory.buckets.iter_mut().enumerate() {
+            if *page_id == bucket_page_id && i as u64 & bit != 0 {
+                *page_id = high_page_id;
+            }
+        }
+        Ok(())
+    }
+
+    /// Repeatedly merge the bucket at directory `index` with its split image
+    /// while both have the same local depth and fit in half a page together.
+    /// Returns whether the directory changed.
+    fn merge_buckets(
+        &self,
+        buffer_manager: &mut BMgr,
+        directory: &mut Directory,
+        index: usize,
+        mut bucket: Bucket,
+    ) -> Result<bool, IndexError> {
+        let mut merged = false;
+        while bucket.local_depth > 0 {
+            let bit = 1 << (bucket.local_depth - 1);
+            let page_id = directory.buckets[index];
+            let image_page_id = directory.buckets[index ^ bit];
+            let image = self.read_bucket(buffer_manager, image_page_id)?;
+            if image.local_depth != bucket.local_depth
+                || bucket.size() + image.size() - BUCKET_HEADER_SIZE > self.page_size / 2
+            {
+                break;
+            }
+
+            // keep the page of the lower image, the other one is dropped
+            let (keep_page_id, drop_page_id) = if index & bit == 0 {
+                (page_id, image_page_id)
+            } else {
+                (image_page_id, page_id)
+            };
+            bucket.local_depth -= 1;
+            bucket.keys.extend(image.keys);
+            bucket.values.extend(image.values);
+            self.write_bucket(buffer_manager, keep_page_id, &bucket)?;
+            for page_id in directory.buckets.iter_mut() {
+                if *page_id == drop_page_id {
+                    *page_id = keep_page_id;
+                }
+            }
+            merged = true;
+        }
+        Ok(merged)
+    }
+
+    fn can_shrink(&self, buffer_manager: &mut BMgr, directory: &Directory) -> Result<bool, IndexError> {
+        let half = 1 << (directory.global_depth - 1);
+        for i in 0..half {
+            if directory.buckets[i] != directory.buckets[i + half] {
+                return Ok(false);
+            }
+            let bucket = self.read_bucket(buffer_manager, directory.buckets[i])?;
+            if bucket.local_depth == directory.global_depth {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn read_directory(&self, buffer_manager: &mut BMgr) -> Result<Directory, IndexError> {
+        let frame_id = buffer_manager
+            .fix_page(self.dir_page_id, false)
+            .ok_or(IndexError::BufferFull)?;
+        let data = buffer_manager.get_data_ref(frame_id);
+        let global_depth = read_u32(data, GLOBAL_DEPTH_OFFSET);
+        let buckets = (0..1 << global_depth)
+            .map(|i| read_u64(data, DIR_HEADER_SIZE + i * 8) as PageId)
+            .collect();
+        buffer_manager.unfix_page(self.dir_page_id);
+        Ok(Directory {
+            global_depth,
+            buckets,
+        })
+    }
+
+    fn write_directory(&self, buffer_manager: &mut BMgr, directory: &Directory) -> Result<(), IndexError> {
+        let frame_id = buffer_manager
+            .fix_page(self.dir_page_id, true)
+            .ok_or(IndexError::BufferFull)?;
+        let data = buffer_manager.get_data(frame_id);
+        write_u32(data, GLOBAL_DEPTH_OFFSET, directory.global_depth);
+        for (i, page_id) in directory.buckets.iter().enumerate() {
+            write_u64(data, DIR_HEADER_SIZE + i * 8, *page_id as u64);
+        }
+        buffer_manager.unfix_page(self.dir_page_id);
+        Ok(())
+    }
+
+    fn read_bucket(&self, buffer_manager: &mut BMgr, page_id: PageId) -> Result<Bucket, IndexError> {
+        let frame_id = buffer_manager
+            .fix_page(page_id, false)
+            .ok_or(IndexError::BufferFull)?;
+        let bucket = Bucket::read(buffer_manager.get_data_ref(frame_id));
+        buffer_manager.unfix_page(page_id);
+        Ok(bucket)
+    }
+
+    fn write_bucket(&self, buffer_manager: &mut BMgr, page_id: PageId, bucket: &Bucket) -> Result<(), IndexError> {
+        let frame_id = buffer_manager
+            .fix_page(page_id, true)
+            .ok_or(IndexError::BufferFull)?;
+        bucket.write(buffer_manager.get_data(frame_id));
+        buffer_manager.unfix_page(page_id);
+        Ok(())
+    }
+
+    fn new_bucket(&self, buffer_manager: &mut BMgr, bucket: &Bucket) -> Result<PageId, IndexError> {
+        let mut page_id = 0;
+        let frame_id = buffer_manager
+            .fix_new_page(&mut page_id)
+            .ok_or(IndexError::BufferFull)?;
+        bucket.write(buffer_manager.get_data(frame_id));
+        buffer_manager.unfix_page(page_id);
+        Ok(page_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index::key_from_u64;
+
+    fn rid(i: u64) -> RecordId {
+        RecordId {
+            page_id: i as PageId,
+            slot: (i % 100) as u16,
+        }
+    }
+
+    #[test]
+    fn extendible_hash_test() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 8);
+        let mut hash_index = ExtendibleHash::create(&mut buffer_manager).unwrap();
+        assert_eq!(hash_index.get_global_depth(&mut buffer_manager).unwrap(), 0);
+
+        let n = 10000;
+        for k in 0..n {
+            assert_eq!(hash_index.insert(&mut buffer_manager, &key_from_u64(k), rid(k)).unwrap(), None);
+        }
+        // 10000 entries of 20 bytes need at least 64 buckets
+        assert!(hash_index.get_global_depth(&mut buffer_manager).unwrap() >= 6);
+        for k in 0..n {
+            assert_eq!(hash_index.get(&mut buffer_manager, &key_from_u64(k)).unwrap(), Some(rid(k)));
+        }
+        assert_eq!(hash_index.get(&mut buffer_manager, &key_from_u64(n)).unwrap(), None);
+        assert_eq!(
+            hash_index.insert(&mut buffer_manager, &key_from_u64(3), rid(4)).unwrap(),
+            Some(rid(3))
+        );
+        assert_eq!(
+            hash_index.insert(&mut buffer_manager, &vec![0; 2000], rid(0)),
+            Err(IndexError::KeyTooLarge)
+        );
+
+        // reopen, then remove everything: buckets merge and the directory shrinks
+        let dir_page_id = hash_index.get_dir_page_id();
+        drop(buffer_manager);
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Clock, 8);
+        let mut hash_index = ExtendibleHash::open(&mut buffer_manager, dir_page_id);
+        assert_eq!(hash_index.get(&mut buffer_manager, &key_from_u64(3)).unwrap(), Some(rid(4)));
+        hash_index.insert(&mut buffer_manager, &key_from_u64(3), rid(3)).unwrap();
+        for k in (0..n).filter(|k| k % 2 == 0) {
+            assert_eq!(hash_index.remove(&mut buffer_manager, &key_from_u64(k)).unwrap(), Some(rid(k)));
+        }
+        assert_eq!(hash_index.remove(&mut buffer_manager, &key_from_u64(0)).unwrap(), None);
+        for k in 0..n {
+            let expect = (k % 2 == 1).then_some(rid(k));
+            assert_eq!(hash_index.get(&mut buffer_manager, &key_from_u64(k)).unwrap(), expect);
+        }
+        for k in (0..n).filter(|k| k % 2 == 1) {
+            assert_eq!(hash_index.remove(&mut buffer_manager, &key_from_u64(k)).unwrap(), Some(rid(k)));
+        }
+        assert_eq!(hash_index.get_global_depth(&mut buffer_manager).unwrap(), 0);
+        assert_eq!(buffer_manager.num_free_frames() + buffer_manager.num_unpinned_frames(), 8);
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn extendible_hash_bytes_test() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Clock, 4);
+        let mut hash_index = ExtendibleHash::create(&mut buffer_manager).unwrap();
+
+        let key = |i: u64| format!("key-{}", i).repeat(1 + (i as usize % 20)).into_bytes();
+        for i in 0..3000 {
+            hash_index.insert(&mut buffer_manager, &key(i), rid(i)).unwrap();
+        }
+        for i in 0..3000 {
+            assert_eq!(hash_index.get(&mut buffer_manager, &key(i)).unwrap(), Some(rid(i)));
+        }
+        for i in (0..3000).step_by(3) {
+            assert_eq!(hash_index.remove(&mut buffer_manager, &key(i)).unwrap(), Some(rid(i)));
+        }
+        for i in 0..3000 {
+            let expect = (i % 3 != 0).then_some(rid(i));
+            assert_eq!(hash_index.get(&mut buffer_manager, &key(i)).unwrap(), expect);
+        }
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+}
@@ -0,0 +1,28 @@
+mod b_plus_tree;
+mod extendible_hash;
+
+use std::fmt;
+
+pub use b_plus_tree::{key_from_u64, BPlusTree, RangeScan};
+pub use extendible_hash::ExtendibleHash;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexError {
+    /// Every frame in the buffer pool is pinned.
+    BufferFull,
+    KeyTooLarge,
+    /// A hash bucket overflowed at the largest directory size.
+    DirectoryFull,
+}
+
+impl fmt::Display for IndexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndexError::BufferFull => write!(f, "no free frame in buffer pool"),
+            IndexError::KeyTooLarge => write!(f, "key too large for index page"),
+            IndexError::DirectoryFull => write!(f, "hash directory cannot grow further"),
+        }
+    }
+}
+
+impl std::error::Error for IndexError {}
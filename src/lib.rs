@@ -0,0 +1,19 @@
+pub mod buffer_access_strategy;
+pub mod buffer_manager;
+pub mod buffer_pool_set;
+pub mod buffer_stats;
+pub mod compression;
+pub mod config;
+pub mod data_storage_manager;
+pub mod encryption;
+pub mod event_recorder;
+pub mod heap_file;
+pub mod index;
+pub mod lock_manager;
+pub mod log_manager;
+pub mod page;
+pub mod recovery;
+pub mod replacer;
+pub mod sharded_buffer_manager;
+pub mod slotted_page;
+pub mod transaction_manager;
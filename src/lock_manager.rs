@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Condvar, Mutex};
+
+use crate::config::*;
+use crate::slotted_page::RecordId;
+use crate::transaction_manager::TxnError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+impl LockMode {
+    fn is_compatible(self, other: LockMode) -> bool {
+        self == LockMode::Shared && other == LockMode::Shared
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockTarget {
+    Page(PageId),
+    Record(RecordId),
+}
+
+#[derive(Default)]
+struct LockQueue {
+    granted: HashMap<TxnId, LockMode>,
+    waiting: Vec<(TxnId, LockMode)>,
+}
+
+#[derive(Default)]
+struct LockTable {
+    queues: HashMap<LockTarget, LockQueue>,
+    held: HashMap<TxnId, HashSet<LockTarget>>,
+    // deadlock victims that have not noticed yet
+    victims: HashSet<TxnId>,
+    num_deadlocks: usize,
+}
+
+impl LockTable {
+    /// The waits-for graph: a waiting transaction points at every other
+    /// transaction holding an incompatible lock on the same target.
+    /// Victims are left out, as they are about to give up their locks.
+    fn waits_for(&self) -> HashMap<TxnId, Vec<TxnId>> {
+        let mut graph: HashMap<TxnId, Vec<TxnId>> = HashMap::new();
+        for queue in self.queues.values() {
+            for &(waiter, mode) in &queue.waiting {
+                if self.victims.contains(&waiter) {
+                    continue;
+                }
+                for (&holder, &held_mode) in &queue.granted {
+                    if holder != waiter
+                        && !mode.is_compatible(held_mode)
+                        && !self.victims.contains(&holder)
+                    {
+                        graph.entry(waiter).or_default().push(holder);
+                    }
+                }
+            }
+        }
+        graph
+    }
+
+    /// If `txn_id` is on a cycle of the waits-for graph, return the youngest
+    /// transaction on it.
+    fn find_deadlock(&self, txn_id: TxnId) -> Option<TxnId> {
+        let graph = self.waits_for();
+        let mut path = vec![txn_id];
+        let mut next = vec![0];
+        let mut visited = HashSet::new();
+        while let Some(&curr) = path.last() {
+            let edges = graph.get(&curr).map(Vec::as_slice).unwrap_or_default();
+            let i = next.last_mut().unwrap();
+            if let Some(&to) = edges.get(*i) {
+                *i += 1;
+                if to == txn_id {
+                    return path.into_iter().max();
+                }
+                if visited.insert(to) {
+                    path.push(to);
+                    next.push(0);
+                }
+            } else {
+                path.pop();
+                next.pop();
+            }
+        }
+        None
+    }
+}
+
+/// Shared and exclusive locks on pages and records. Locks are held until
+/// `unlock_all`, which transactions call when they commit or abort (strict
+/// 2PL). A request that closes a cycle in the waits-for graph aborts the
+/// youngest transaction on the cycle; its pending `lock` fails with
+/// `TxnError::Deadlock`.
+pub struct LockManager {
+    table: Mutex<LockTable>,
+    changed: Condvar,
+}
+
+impl LockManager {
+    pub fn new() -> LockManager {
+        LockManager {
+            table: Mutex::new(LockTable::default()),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Block until `txn_id` holds `target` in `mode`. Asking for an
+    /// exclusive lock on a target held shared upgrades it.
+    pub fn lock(&self, txn_id: TxnId, target: LockTarget, mode: LockMode) -> Result<(), TxnError> {
+        let mut table = self.table.lock().unwrap();
+        loop {
+            if table.victims.remove(&txn_id) {
+                let queue = table.queues.get_mut(&target).unwrap();
+                queue.waiting.retain(|&(waiter, _)| waiter != txn_id);
+                return Err(TxnError::Deadlock);
+            }
+
+            let queue = table.queues.entry(target).or_default();
+            match queue.granted.get(&txn_id) {
+                Some(LockMode::Exclusive) => return Ok(()),
+                Some(LockMode::Shared) if mode == LockMode::Shared => return Ok(()),
+                _ => {}
+            }
+            let grantable = queue
+                .granted
+                .iter()
+                .all(|(&holder, &held_mode)| holder == txn_id || mode.is_compatible(held_mode));
+            if grantable {
+                queue.waiting.retain(|&(waiter, _)| waiter != txn_id);
+                queue.granted.insert(txn_id, mode);
+                table.held.entry(txn_id).or_default().insert(target);
+                return Ok(());
+            }
+            if !queue.waiting.iter().any(|&(waiter, _)| waiter == txn_id) {
+                queue.waiting.push((txn_id, mode));
+            }
+
+            if let Some(victim) = table.find_deadlock(txn_id) {
+                table.num_deadlocks += 1;
+                table.victims.insert(victim);
+                self.changed.notify_all();
+                continue;
+            }
+            table = self.changed.wait(table).unwrap();
+        }
+    }
+
+    /// Release every lock of `txn_id`.
+    pub fn unlock_all(&self, txn_id: TxnId) {
+        let mut table = self.table.lock().unwrap();
+        for target in table.held.remove(&txn_id).unwrap_or_default() {
+            let queue = table.queues.get_mut(&target).unwrap();
+            queue.granted.remove(&txn_id);
+            if queue.granted.is_empty() && queue.waiting.is_empty() {
+                table.queues.remove(&target);
+            }
+        }
+        table.victims.remove(&txn_id);
+        self.changed.notify_all();
+    }
+
+    pub fn get_lock_mode(&self, txn_id: TxnId, target: LockTarget) -> Option<LockMode> {
+        let table = self.table.lock().unwrap();
+        table.queues.get(&target)?.granted.get(&txn_id).copied()
+    }
+
+    pub fn get_deadlock_num(&self) -> usize {
+        self.table.lock().unwrap().num_deadlocks
+    }
+}
+
+impl Default for LockManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn lock_manager_test() {
+        let lock_manager = LockManager::new();
+        let page = LockTarget::Page(1);
+        let record = LockTarget::Record(RecordId { page_id: 1, slot: 0 });
+
+        // shared locks are compatible, and a sole holder may upgrade
+        lock_manager.lock(1, page, LockMode::Shared).unwrap();
+        lock_manager.lock(2, page, LockMode::Shared).unwrap();
+        lock_manager.lock(2, record, LockMode::Exclusive).unwrap();
+        lock_manager.unlock_all(1);
+        assert_eq!(lock_manager.get_lock_mode(1, page), None);
+        lock_manager.lock(2, page, LockMode::Exclusive).unwrap();
+        assert_eq!(lock_manager.get_lock_mode(2, page), Some(LockMode::Exclusive));
+
+        // an exclusive lock blocks until the holder releases it
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| lock_manager.lock(3, record, LockMode::Shared));
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            lock_manager.unlock_all(2);
+            waiter.join().unwrap().unwrap();
+        });
+        assert_eq!(lock_manager.get_lock_mode(3, record), Some(LockMode::Shared));
+    }
+
+    #[test]
+    fn deadlock_test() {
+        let lock_manager = LockManager::new();
+        let barrier = Barrier::new(2);
+        let (first, second) = (LockTarget::Page(1), LockTarget::Page(2));
+
+        let results = std::thread::scope(|s| {
+            let run = |txn_id: TxnId, held: LockTarget, wanted: LockTarget| {
+                let lock_manager = &lock_manager;
+                let barrier = &barrier;
+                s.spawn(move || {
+                    lock_manager.lock(txn_id, held, LockMode::Exclusive).unwrap();
+                    barrier.wait();
+                    let result = lock_manager.lock(txn_id, wanted, LockMode::Exclusive);
+                    // the victim aborts, releasing its locks
+                    lock_manager.unlock_all(txn_id);
+                    result
+                })
+            };
+            let older = run(1, first, second);
+            let younger = run(2, second, first);
+            (older.join().unwrap(), younger.join().unwrap())
+        });
+        assert!(matches!(results, (Ok(()), Err(TxnError::Deadlock))));
+        assert_eq!(lock_manager.get_deadlock_num(), 1);
+    }
+}
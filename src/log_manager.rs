@@ -0,0 +1,466 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::FileExt;
+use std::sync::Mutex;
+
+use crate::config::*;
+
+const LOG_MAGIC: &[u8; 8] = b"ADBSLOG\0";
+// The log file header is | magic | master_lsn: u64 |, where the master
+// record is the LSN of the last complete checkpoint's begin record.
+const MASTER_LSN_OFFSET: u64 = 8;
+/// The LSN of the first record; 0 is never a valid LSN.
+pub const FIRST_LSN: Lsn = 16;
+pub const INVALID_LSN: Lsn = 0;
+
+// Record layout (little endian):
+// | len: u32 | checksum: u32 | lsn: u64 | prev_lsn: u64 | txn_id: u64 | kind: u8 | body |
+// `len` covers the whole record and the checksum covers everything after it.
+// An update body is | page_id: u64 | offset: u32 | len: u32 | before | after |,
+// a compensation body is | page_id: u64 | offset: u32 | len: u32 | undo_next_lsn: u64 | after |
+// and an end checkpoint body is | num_txns: u32 | num_pages: u32 | (txn_id, last_lsn)* |
+// (page_id, rec_lsn)* | with u64 fields.
+const RECORD_HEADER_SIZE: usize = 37;
+const BEGIN: u8 = 1;
+const COMMIT: u8 = 2;
+const ABORT: u8 = 3;
+const UPDATE: u8 = 4;
+const COMPENSATION: u8 = 5;
+const END: u8 = 6;
+const BEGIN_CHECKPOINT: u8 = 7;
+const END_CHECKPOINT: u8 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogBody {
+    Begin,
+    Commit,
+    Abort,
+    /// Bytes `offset..offset + before.len()` of a page changed from `before`
+    /// (undo image) to `after` (redo image).
+    Update {
+        page_id: PageId,
+        offset: usize,
+        before: Vec<u8>,
+        after: Vec<u8>,
+    },
+    /// Redo-only record written while undoing an update; `undo_next_lsn`
+    /// is the next record of the transaction left to undo.
+    Compensation {
+        page_id: PageId,
+        offset: usize,
+        after: Vec<u8>,
+        undo_next_lsn: Lsn,
+    },
+    /// The transaction is finished, after a commit or a complete rollback.
+    End,
+    BeginCheckpoint,
+    /// The active transactions with their last LSN and the dirty pages with
+    /// their recovery LSN, as of some point after the matching begin record.
+    EndCheckpoint {
+        txns: Vec<(TxnId, Lsn)>,
+        dirty_pages: Vec<(PageId, Lsn)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub lsn: Lsn,
+    /// The previous record of the same transaction, or `INVALID_LSN`.
+    pub prev_lsn: Lsn,
+    pub txn_id: TxnId,
+    pub body: LogBody,
+}
+
+impl LogRecord {
+    /// The size of the encoded record; the next record starts at `lsn + size()`.
+    pub fn size(&self) -> usize {
+        match &self.body {
+            LogBody::Update { before, .. } => RECORD_HEADER_SIZE + 16 + 2 * before.len(),
+            LogBody::Compensation { after, .. } => RECORD_HEADER_SIZE + 24 + after.len(),
+            LogBody::EndCheckpoint { txns, dirty_pages } => {
+                RECORD_HEADER_SIZE + 8 + 16 * (txns.len() + dirty_pages.len())
+            }
+            _ => RECORD_HEADER_SIZE,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![0; RECORD_HEADER_SIZE];
+        buf[8..16].copy_from_slice(&self.lsn.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.prev_lsn.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.txn_id.to_le_bytes());
+        buf[32] = match &self.body {
+            LogBody::Begin => BEGIN,
+            LogBody::Commit => COMMIT,
+            LogBody::Abort => ABORT,
+            LogBody::Update {
+                page_id,
+                offset,
+                before,
+                after,
+            } => {
+                assert_eq!(before.len(), after.len());
+                buf.extend_from_slice(&(*page_id as u64).to_le_bytes());
+                buf.extend_from_slice(&(*offset as u32).to_le_bytes());
+                buf.extend_from_slice(&(before.len() as u32).to_le_bytes());
+                buf.extend_from_slice(before);
+                buf.extend_from_slice(after);
+                UPDATE
+            }
+            LogBody::Compensation {
+                page_id,
+                offset,
+                after,
+                undo_next_lsn,
+            } => {
+                buf.extend_from_slice(&(*page_id as u64).to_le_bytes());
+                buf.extend_from_slice(&(*offset as u32).to_le_bytes());
+                buf.extend_from_slice(&(after.len() as u32).to_le_bytes());
+                buf.extend_from_slice(&undo_next_lsn.to_le_bytes());
+                buf.extend_from_slice(after);
+                COMPENSATION
+            }
+            LogBody::End => END,
+            LogBody::BeginCheckpoint => BEGIN_CHECKPOINT,
+            LogBody::EndCheckpoint { txns, dirty_pages } => {
+                buf.extend_from_slice(&(txns.len() as u32).to_le_bytes());
+                buf.extend_from_slice(&(dirty_pages.len() as u32).to_le_bytes());
+                for &(txn_id, lsn) in txns {
+                    buf.extend_from_slice(&txn_id.to_le_bytes());
+                    buf.extend_from_slice(&lsn.to_le_bytes());
+                }
+                for &(page_id, lsn) in dirty_pages {
+                    buf.extend_from_slice(&(page_id as u64).to_le_bytes());
+                    buf.extend_from_slice(&lsn.to_le_bytes());
+                }
+                END_CHECKPOINT
+            }
+        };
+        let len = buf.len() as u32;
+        buf[0..4].copy_from_slice(&len.to_le_bytes());
+        let checksum = checksum(&buf[8..]);
+        buf[4..8].copy_from_slice(&checksum.to_le_bytes());
+        buf
+    }
+
+    /// Decode a whole record, or `None` if it is corrupt or torn.
+    fn decode(buf: &[u8]) -> Option<LogRecord> {
+        if buf.len() < RECORD_HEADER_SIZE
+            || u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize != buf.len()
+            || u32::from_le_bytes(buf[4..8].try_into().unwrap()) != checksum(&buf[8..])
+        {
+            return None;
+        }
+        let u64_at = |pos: usize| u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap());
+        let u32_at = |pos: usize| u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap()) as usize;
+
+        let body = match buf[32] {
+            BEGIN => LogBody::Begin,
+            COMMIT => LogBody::Commit,
+            ABORT => LogBody::Abort,
+            UPDATE => {
+                let pos = RECORD_HEADER_SIZE;
+                let len = u32_at(pos + 12);
+                let before = pos + 16;
+                LogBody::Update {
+                    page_id: u64_at(pos) as PageId,
+                    offset: u32_at(pos + 8),
+                    before: buf.get(before..before + len)?.to_vec(),
+                    after: buf.get(before + len..before + 2 * len)?.to_vec(),
+                }
+            }
+            COMPENSATION => {
+                let pos = RECORD_HEADER_SIZE;
+                let len = u32_at(pos + 12);
+                let after = buf.get(pos + 24..pos + 24 + len)?.to_vec();
+                LogBody::Compensation {
+                    page_id: u64_at(pos) as PageId,
+                    offset: u32_at(pos + 8),
+                    undo_next_lsn: u64_at(pos + 16),
+                    after,
+                }
+            }
+            END => LogBody::End,
+            BEGIN_CHECKPOINT => LogBody::BeginCheckpoint,
+            END_CHECKPOINT => {
+                let pos = RECORD_HEADER_SIZE;
+                let num_txns = u32_at(pos);
+                let num_pages = u32_at(pos + 4);
+                if buf.len() != pos + 8 + 16 * (num_txns + num_pages) {
+                    return None;
+                }
+                let pair = |i: usize| (u64_at(pos + 8 + 16 * i), u64_at(pos + 16 + 16 * i));
+                LogBody::EndCheckpoint {
+                    txns: (0..num_txns).map(pair).collect(),
+                    dirty_pages: (num_txns..num_txns + num_pages)
+                        .map(|i| {
+                            let (page_id, lsn) = pair(i);
+                            (page_id as PageId, lsn)
+                        })
+                        .collect(),
+                }
+            }
+            _ => return None,
+        };
+        Some(LogRecord {
+            lsn: u64_at(8),
+            prev_lsn: u64_at(16),
+            txn_id: u64_at(24),
+            body,
+        })
+    }
+}
+
+/// 32-bit FNV-1a.
+fn checksum(buf: &[u8]) -> u32 {
+    buf.iter()
+        .fold(0x811c9dc5, |h, &b| (h ^ b as u32).wrapping_mul(0x01000193))
+}
+
+struct LogInner {
+    file: File,
+    /// Records appended after `flushed_lsn`, not yet on disk.
+    buffer: Vec<u8>,
+    next_lsn: Lsn,
+    flushed_lsn: Lsn,
+    num_flushes: usize,
+}
+
+/// An append-only write-ahead log. The LSN of a record is its offset in the
+/// log file, so LSNs grow monotonically and a record is durable once its
+/// LSN is below `get_flushed_lsn`.
+pub struct LogManager {
+    inner: Mutex<LogInner>,
+}
+
+impl LogManager {
+    /// Open or create the log at `path`. A torn record at the end of an
+    /// existing log, left by a crash during a flush, is cut off.
+    pub fn open(path: &str) -> Result<LogManager, std::io::Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let file_len = file.metadata()?.len();
+        if file_len == 0 {
+            let mut header = [0; FIRST_LSN as usize];
+            header[..LOG_MAGIC.len()].copy_from_slice(LOG_MAGIC);
+            file.write_all_at(&header, 0)?;
+            file.sync_all()?;
+        } else {
+            let mut magic = [0; LOG_MAGIC.len()];
+            file.read_exact_at(&mut magic, 0)?;
+            if &magic != LOG_MAGIC {
+                return Err(Error::new(ErrorKind::InvalidData, "not a log file"));
+            }
+        }
+
+        let mut end = FIRST_LSN;
+        while let Some(record) = Self::read_from_file(&file, end)? {
+            end += record.size() as Lsn;
+        }
+        if end < file_len.max(FIRST_LSN) {
+            file.set_len(end)?;
+            file.sync_all()?;
+        }
+
+        Ok(LogManager {
+            inner: Mutex::new(LogInner {
+                file,
+                buffer: Vec::new(),
+                next_lsn: end,
+                flushed_lsn: end,
+                num_flushes: 0,
+            }),
+        })
+    }
+
+    /// Append a record to the log buffer and return its LSN.
+    pub fn append(&self, prev_lsn: Lsn, txn_id: TxnId, body: LogBody) -> Lsn {
+        let mut inner = self.inner.lock().unwrap();
+        let lsn = inner.next_lsn;
+        let record = LogRecord {
+            lsn,
+            prev_lsn,
+            txn_id,
+            body,
+        };
+        let buf = record.encode();
+        inner.next_lsn += buf.len() as Lsn;
+        inner.buffer.extend_from_slice(&buf);
+        lsn
+    }
+
+    /// Make the record at `lsn`, and every record before it, durable.
+    pub fn flush(&self, lsn: Lsn) -> Result<(), std::io::Error> {
+        let mut inner = self.inner.lock().unwrap();
+        if lsn < inner.flushed_lsn || inner.buffer.is_empty() {
+            return Ok(());
+        }
+        inner.file.write_all_at(&inner.buffer, inner.flushed_lsn)?;
+        inner.file.sync_data()?;
+        inner.flushed_lsn = inner.next_lsn;
+        inner.buffer.clear();
+        inner.num_flushes += 1;
+        Ok(())
+    }
+
+    pub fn flush_all(&self) -> Result<(), std::io::Error> {
+        let next_lsn = self.get_next_lsn();
+        self.flush(next_lsn)
+    }
+
+    /// Read the record at `lsn`, from the log buffer if it is not flushed yet.
+    pub fn read_record(&self, lsn: Lsn) -> Result<Option<LogRecord>, std::io::Error> {
+        let inner = self.inner.lock().unwrap();
+        if lsn >= inner.flushed_lsn {
+            let pos = (lsn - inner.flushed_lsn) as usize;
+            let Some(len) = inner.buffer.get(pos..pos + 4) else {
+                return Ok(None);
+            };
+            let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+            return Ok(LogRecord::decode(&inner.buffer[pos..pos + len]));
+        }
+        Self::read_from_file(&inner.file, lsn)
+    }
+
+    fn read_from_file(file: &File, lsn: Lsn) -> Result<Option<LogRecord>, std::io::Error> {
+        let mut len = [0; 4];
+        match file.read_exact_at(&mut len, lsn) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        if len < RECORD_HEADER_SIZE {
+            return Ok(None);
+        }
+        let mut buf = vec![0; len];
+        match file.read_exact_at(&mut buf, lsn) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        Ok(LogRecord::decode(&buf).filter(|record| record.lsn == lsn))
+    }
+
+    /// The begin record of the last complete checkpoint, or `INVALID_LSN`.
+    pub fn get_master_lsn(&self) -> Result<Lsn, std::io::Error> {
+        let inner = self.inner.lock().unwrap();
+        let mut buf = [0; 8];
+        inner.file.read_exact_at(&mut buf, MASTER_LSN_OFFSET)?;
+        Ok(Lsn::from_le_bytes(buf))
+    }
+
+    /// Durably point the master record at a checkpoint whose end record
+    /// is already flushed.
+    pub fn set_master_lsn(&self, lsn: Lsn) -> Result<(), std::io::Error> {
+        let inner = self.inner.lock().unwrap();
+        assert!(lsn < inner.flushed_lsn);
+        inner.file.write_all_at(&lsn.to_le_bytes(), MASTER_LSN_OFFSET)?;
+        inner.file.sync_data()
+    }
+
+    /// Iterate over the records from `lsn` to the end of the log.
+    pub fn iter_from(&self, lsn: Lsn) -> LogIter<'_> {
+        LogIter {
+            log_manager: self,
+            lsn,
+        }
+    }
+
+    /// Records with an LSN below this are on disk.
+    pub fn get_flushed_lsn(&self) -> Lsn {
+        self.inner.lock().unwrap().flushed_lsn
+    }
+
+    /// The LSN the next appended record will get.
+    pub fn get_next_lsn(&self) -> Lsn {
+        self.inner.lock().unwrap().next_lsn
+    }
+
+    pub fn get_flush_num(&self) -> usize {
+        self.inner.lock().unwrap().num_flushes
+    }
+}
+
+pub struct LogIter<'a> {
+    log_manager: &'a LogManager,
+    lsn: Lsn,
+}
+
+impl Iterator for LogIter<'_> {
+    type Item = Result<LogRecord, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.log_manager.read_record(self.lsn) {
+            Ok(Some(record)) => {
+                self.lsn += record.size() as Lsn;
+                Some(Ok(record))
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn log_manager_test() {
+        let file = format!("./target/test_file_{:?}.log", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let log_manager = LogManager::open(&file).unwrap();
+        assert_eq!(log_manager.get_next_lsn(), FIRST_LSN);
+
+        let begin = log_manager.append(INVALID_LSN, 1, LogBody::Begin);
+        let update_body = LogBody::Update {
+            page_id: 7,
+            offset: 100,
+            before: b"old".to_vec(),
+            after: b"new".to_vec(),
+        };
+        let update = log_manager.append(begin, 1, update_body.clone());
+        assert!(begin < update);
+        assert_eq!(log_manager.get_flushed_lsn(), FIRST_LSN);
+        // readable before it is flushed
+        let record = log_manager.read_record(update).unwrap().unwrap();
+        assert_eq!(record.prev_lsn, begin);
+        assert_eq!(record.body, update_body);
+
+        log_manager.flush(begin).unwrap();
+        assert!(log_manager.get_flushed_lsn() > update);
+        assert_eq!(log_manager.get_flush_num(), 1);
+        // already durable, no second flush
+        log_manager.flush(update).unwrap();
+        assert_eq!(log_manager.get_flush_num(), 1);
+        let commit = log_manager.append(update, 1, LogBody::Commit);
+        let checkpoint = log_manager.append(INVALID_LSN, 0, LogBody::BeginCheckpoint);
+        let checkpoint_body = LogBody::EndCheckpoint {
+            txns: vec![(2, update)],
+            dirty_pages: vec![(7, update), (9, commit)],
+        };
+        let end_checkpoint = log_manager.append(INVALID_LSN, 0, checkpoint_body.clone());
+        log_manager.flush_all().unwrap();
+        assert_eq!(log_manager.get_master_lsn().unwrap(), INVALID_LSN);
+        log_manager.set_master_lsn(checkpoint).unwrap();
+        let end = log_manager.get_next_lsn();
+        drop(log_manager);
+
+        // a torn record at the end is cut off on open
+        let f = OpenOptions::new().write(true).open(&file).unwrap();
+        f.write_all_at(&[40, 0, 0, 0, 1, 2, 3], end).unwrap();
+        drop(f);
+        let log_manager = LogManager::open(&file).unwrap();
+        assert_eq!(log_manager.get_next_lsn(), end);
+        assert_eq!(log_manager.read_record(begin).unwrap().unwrap().body, LogBody::Begin);
+        assert_eq!(log_manager.read_record(update).unwrap().unwrap().body, update_body);
+        assert_eq!(log_manager.read_record(commit).unwrap().unwrap().body, LogBody::Commit);
+        assert_eq!(log_manager.read_record(end_checkpoint).unwrap().unwrap().body, checkpoint_body);
+        assert_eq!(log_manager.read_record(end).unwrap(), None);
+        assert_eq!(log_manager.get_master_lsn().unwrap(), checkpoint);
+        let _ = std::fs::remove_file(file);
+    }
+}
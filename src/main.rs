@@ -1,14 +1,10 @@
 use std::error::Error;
-use std::io::{Write, BufReader, BufRead};
+use std::io::{BufReader, BufRead};
 use std::{env, fs, process};
-use crate::config::*;
-
-mod config;
-mod buffer_manager;
-mod data_storage_manager;
-mod page;
-
-mod replacer;
+use ustc_adbs_lab_rust::config::*;
+use std::sync::{Arc, Mutex};
+use ustc_adbs_lab_rust::page::PAGE_HEADER_SIZE;
+use ustc_adbs_lab_rust::{buffer_manager, data_storage_manager, event_recorder, log_manager, transaction_manager};
 
 fn main() {
     let run_time = std::time::Instant::now();
@@ -20,10 +16,14 @@ fn main() {
     });
 
     {
-        // write 50000 * PAGE_SIZE bytes to file
-        let mut file = std::fs::File::create(DB_FILE_NAME).unwrap();
-        let buf = vec![0 as u8; 50000 * PAGE_SIZE];
-        file.write_all(&buf).unwrap();
+        // create a fresh file with 50000 zeroed pages
+        let _ = std::fs::remove_file(DB_FILE_NAME);
+        let data_storage_manager = data_storage_manager::DSMgr::open(DB_FILE_NAME, storage_options(config.page_size))
+            .unwrap_or_else(|err| {
+                println!("cannot create {}: {}", DB_FILE_NAME, err);
+                process::exit(1);
+            });
+        data_storage_manager.new_pages(50000);
     }
 
     if let Err(e) = run(config) {
@@ -33,29 +33,76 @@ fn main() {
     println!("procee run time: {} ms", run_time.elapsed().as_millis());
 }
 
+fn storage_options(page_size: usize) -> StorageOptions {
+    StorageOptions {
+        sync_policy: SYNC_POLICY,
+        direct_io: DIRECT_IO,
+        page_size,
+        double_write_pages: DOUBLE_WRITE_PAGES,
+        compression: COMPRESSION,
+        segment_pages: SEGMENT_PAGES,
+    }
+}
+
 fn run(config: Config) -> Result<(), Box<dyn Error>> {
     let content = fs::read_to_string(&config.file_path)?;
 
-    let mut buffer_manager = buffer_manager::BMgr::new(DB_FILE_NAME, config.policy, FRAME_NUM);
+    let data_storage_manager = data_storage_manager::DSMgr::open(DB_FILE_NAME, storage_options(config.page_size))?;
+    let mut buffer_manager =
+        buffer_manager::BMgr::with_storage(data_storage_manager, config.policy, FRAME_NUM);
+    if EVENT_LOG {
+        buffer_manager.set_event_recorder(event_recorder::EventRecorder::create(EVENT_LOG_FILE_NAME)?);
+    }
     let data_file = std::fs::File::open(&config.file_path).unwrap();
     let reader = BufReader::new(data_file);
 
     let trace_time = std::time::Instant::now();
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let parts: Vec<&str> = line.split(",").collect();
-        
-        let is_dirty = parts[0].parse::<u8>().unwrap() != 0;
-        let page_id = parts[1].parse::<PageId>().unwrap() - 1;
-        buffer_manager.fix_page(page_id, is_dirty);
-        buffer_manager.unfix_page(page_id);
+    if let Some((steal_policy, force_policy)) = config.txn_policy {
+        // every TXN_SIZE accesses form a transaction; writes are logged
+        let _ = std::fs::remove_file(LOG_FILE_NAME);
+        let log_manager = Arc::new(log_manager::LogManager::open(LOG_FILE_NAME)?);
+        buffer_manager.set_log_manager(log_manager.clone());
+        buffer_manager.set_steal_policy(steal_policy);
+        buffer_manager.set_force_policy(force_policy);
+        let transaction_manager = transaction_manager::TransactionManager::new(log_manager.clone());
+        let buffer_pool = Mutex::new(buffer_manager);
+
+        let lines: Vec<String> = reader.lines().map(|line| line.unwrap()).collect();
+        for chunk in lines.chunks(TXN_SIZE) {
+            let txn_id = transaction_manager.begin();
+            for line in chunk {
+                let parts: Vec<&str> = line.split(",").collect();
+                let is_dirty = parts[0].parse::<u8>().unwrap() != 0;
+                let page_id = parts[1].parse::<PageId>().unwrap() - 1;
+                if is_dirty {
+                    transaction_manager.write(&buffer_pool, txn_id, page_id, PAGE_HEADER_SIZE, &[1])?;
+                } else {
+                    transaction_manager.read(&buffer_pool, txn_id, page_id, PAGE_HEADER_SIZE, 1)?;
+                }
+            }
+            transaction_manager.commit(&mut buffer_pool.lock().unwrap(), txn_id)?;
+        }
+        buffer_manager = buffer_pool.into_inner().unwrap();
+        println!("log flush number: {}", log_manager.get_flush_num());
+    } else {
+        for line in reader.lines() {
+            let line = line.unwrap();
+            let parts: Vec<&str> = line.split(",").collect();
+
+            let is_dirty = parts[0].parse::<u8>().unwrap() != 0;
+            let page_id = parts[1].parse::<PageId>().unwrap() - 1;
+            buffer_manager.fix_page(page_id, is_dirty);
+            buffer_manager.unfix_page(page_id);
+        }
     }
     println!("read io: {}", buffer_manager.get_read_io_num());
     println!("write io: {}", buffer_manager.get_write_io_num());
     println!("total io: {}", buffer_manager.get_io_num());
     println!("hit number: {}", buffer_manager.get_hit_num());
+    println!("sync number: {}", buffer_manager.get_sync_num());
     println!("hit rate: {}%", buffer_manager.get_hit_num() as f64 / content.lines().count() as f64 * 100.0);
     println!("trace time: {} ms", trace_time.elapsed().as_millis());
+    println!("stats: {}", buffer_manager.get_stats().to_json());
 
     Ok(())
 }
@@ -1,21 +1,123 @@
-use std::usize;
+use std::{
+    alloc::{self, Layout},
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
 
 use crate::config::*;
 
+/// A zeroed heap buffer aligned to `PAGE_ALIGN`, as required by `O_DIRECT`
+/// I/O. The buffer manager keeps all frames in one of these.
+pub struct AlignedBuf {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl AlignedBuf {
+    pub fn new(len: usize) -> AlignedBuf {
+        assert!(len > 0 && len.is_multiple_of(PAGE_ALIGN));
+        let layout = Layout::from_size_align(len, PAGE_ALIGN).unwrap();
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        let ptr = NonNull::new(ptr).unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        AlignedBuf { ptr, len }
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for AlignedBuf {
+    fn drop(&mut self) {
+        let layout = Layout::from_size_align(self.len, PAGE_ALIGN).unwrap();
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), layout) };
+    }
+}
+
+// AlignedBuf owns its allocation exclusively, like a Box<[u8]>
+unsafe impl Send for AlignedBuf {}
+unsafe impl Sync for AlignedBuf {}
+
+// Every page starts with a common header:
+// | page_lsn: u64 | checksum: u32 | reserved: u32 | write_version: u64 |
+// Page layouts (slotted pages, index nodes, ...) start after it.
+pub const PAGE_LSN_OFFSET: usize = 0;
+pub const PAGE_CHECKSUM_OFFSET: usize = 8;
+pub const PAGE_VERSION_OFFSET: usize = 16;
+pub const PAGE_HEADER_SIZE: usize = 24;
+
+/// The LSN of the last log record applied to the page.
+pub fn get_page_lsn(data: &Data) -> Lsn {
+    read_u64(data, PAGE_LSN_OFFSET)
+}
+
+pub fn set_page_lsn(data: &mut Data, lsn: Lsn) {
+    write_u64(data, PAGE_LSN_OFFSET, lsn);
+}
+
+/// A 32-bit checksum of `bytes`, continuing from `seed` (0 to start).
+pub fn compute_checksum(seed: u32, bytes: &[u8]) -> u32 {
+    let mut h = 0xcbf29ce484222325u64 ^ seed as u64;
+    let mut words = bytes.chunks_exact(8);
+    for word in &mut words {
+        h = (h ^ u64::from_le_bytes(word.try_into().unwrap())).wrapping_mul(0x100000001b3);
+        h ^= h >> 29;
+    }
+    for &b in words.remainder() {
+        h = (h ^ b as u64).wrapping_mul(0x100000001b3);
+    }
+    (h ^ (h >> 32)) as u32
+}
+
+/// The checksum of a page, covering everything but the checksum field.
+pub fn page_checksum(data: &Data) -> u32 {
+    let seed = compute_checksum(0, &data[..PAGE_CHECKSUM_OFFSET]);
+    compute_checksum(seed, &data[PAGE_CHECKSUM_OFFSET + 4..])
+}
+
+pub fn set_page_checksum(data: &mut Data) {
+    let checksum = page_checksum(data);
+    write_u32(data, PAGE_CHECKSUM_OFFSET, checksum);
+}
+
+/// Whether the stored checksum matches. A page that was allocated but
+/// never written is all zeros and counts as valid.
+pub fn verify_page_checksum(data: &Data) -> bool {
+    read_u32(data, PAGE_CHECKSUM_OFFSET) == page_checksum(data) || data.iter().all(|&b| b == 0)
+}
+
+/// Frame metadata; the frame's data lives in the buffer manager's slab.
 pub struct Page {
     page_id: Option<PageId>,
-    data: Data,
     is_dirty: bool,
     pin_count: usize,
+    // LSN of the first logged update since the page was last written out
+    rec_lsn: Option<Lsn>,
+    // transactions with logged updates on the page that have not finished
+    txns: Vec<TxnId>,
+    // how the page was brought into the pool, for the replacer
+    access_type: AccessType,
 }
 
 impl Page {
     pub fn new(page_id: Option<PageId>) -> Page {
         Page {
             page_id,
-            data: [0; PAGE_SIZE],
             is_dirty: false,
             pin_count: 0,
+            rec_lsn: None,
+            txns: Vec::new(),
+            access_type: AccessType::Normal,
         }
     }
 
@@ -27,16 +129,51 @@ impl Page {
         self.page_id = Some(page_id);
     }
 
-    pub fn get_data(&mut self) -> &mut Data {
-        &mut self.data
-    }
-
     pub fn is_dirty(&self) -> bool {
         self.is_dirty
     }
 
+    /// Cleaning a page also clears its recovery LSN.
     pub fn set_dirty(&mut self, is_dirty: bool) {
         self.is_dirty = is_dirty;
+        if !is_dirty {
+            self.rec_lsn = None;
+        }
+    }
+
+    pub fn get_rec_lsn(&self) -> Option<Lsn> {
+        self.rec_lsn
+    }
+
+    /// Record `lsn` as the recovery LSN unless the page already has one.
+    pub fn set_rec_lsn(&mut self, lsn: Lsn) {
+        self.rec_lsn.get_or_insert(lsn);
+    }
+
+    pub fn get_txns(&self) -> &[TxnId] {
+        &self.txns
+    }
+
+    pub fn add_txn(&mut self, txn_id: TxnId) {
+        if !self.txns.contains(&txn_id) {
+            self.txns.push(txn_id);
+        }
+    }
+
+    pub fn remove_txn(&mut self, txn_id: TxnId) {
+        self.txns.retain(|&t| t != txn_id);
+    }
+
+    pub fn clear_txns(&mut self) {
+        self.txns.clear();
+    }
+
+    pub fn get_access_type(&self) -> AccessType {
+        self.access_type
+    }
+
+    pub fn set_access_type(&mut self, access_type: AccessType) {
+        self.access_type = access_type;
     }
 
     pub fn get_pin_count(&self) -> usize {
@@ -50,4 +187,30 @@ impl Page {
     pub fn decrement_pin_count(&mut self) {
         self.pin_count -= 1;
     }
-}
\ No newline at end of file
+}
+
+// little-endian field accessors for on-page layouts
+
+pub fn read_u16(data: &Data, pos: usize) -> u16 {
+    u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap())
+}
+
+pub fn write_u16(data: &mut Data, pos: usize, value: u16) {
+    data[pos..pos + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn read_u32(data: &Data, pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+pub fn write_u32(data: &mut Data, pos: usize, value: u32) {
+    data[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn read_u64(data: &Data, pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+pub fn write_u64(data: &mut Data, pos: usize, value: u64) {
+    data[pos..pos + 8].copy_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,373 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+
+use crate::buffer_manager::BMgr;
+use crate::config::*;
+use crate::log_manager::*;
+use crate::page::*;
+
+#[derive(Debug, Default)]
+pub struct RecoveryStats {
+    /// Where redo started, or `INVALID_LSN` if nothing was dirty.
+    pub redo_lsn: Lsn,
+    /// Updates reapplied because the page on disk was older than the log.
+    pub num_redone: usize,
+    /// Updates rolled back, each with a compensation record.
+    pub num_undone: usize,
+    /// Transactions that were active at the crash and have been rolled back.
+    pub losers: Vec<TxnId>,
+}
+
+/// ARIES restart recovery over the buffer manager's log:
+///
+/// 1. analysis rebuilds the transaction table and the dirty page table,
+///    starting from the last checkpoint,
+/// 2. redo repeats history from the smallest recovery LSN, skipping updates
+///    whose page LSN shows they already reached disk,
+/// 3. undo rolls back the loser transactions, writing compensation records
+///    so a crash during recovery never undoes an update twice.
+///
+/// The pages touched are left dirty in the pool; the log is flushed.
+pub fn recover(buffer_manager: &mut BMgr) -> Result<RecoveryStats, Error> {
+    let log_manager = buffer_manager
+        .get_log_manager()
+        .expect("no log manager")
+        .clone();
+
+    // analysis, from the last checkpoint if there is one
+    let master_lsn = log_manager.get_master_lsn()?;
+    let start_lsn = if master_lsn == INVALID_LSN {
+        FIRST_LSN
+    } else {
+        master_lsn
+    };
+    let mut txn_table: HashMap<TxnId, Lsn> = HashMap::new();
+    let mut dirty_page_table: HashMap<PageId, Lsn> = HashMap::new();
+    // transactions finished after the checkpoint began may still be in it
+    let mut ended: HashSet<TxnId> = HashSet::new();
+    for record in log_manager.iter_from(start_lsn) {
+        let record = record?;
+        match record.body {
+            LogBody::Commit | LogBody::End => {
+                txn_table.remove(&record.txn_id);
+                ended.insert(record.txn_id);
+            }
+            LogBody::Update { page_id, .. } | LogBody::Compensation { page_id, .. } => {
+                txn_table.insert(record.txn_id, record.lsn);
+                dirty_page_table.entry(page_id).or_insert(record.lsn);
+            }
+            LogBody::Begin | LogBody::Abort => {
+                txn_table.insert(record.txn_id, record.lsn);
+            }
+            LogBody::BeginCheckpoint => {}
+            LogBody::EndCheckpoint { txns, dirty_pages } => {
+                for (txn_id, last_lsn) in txns {
+                    if !ended.contains(&txn_id) {
+                        txn_table.entry(txn_id).or_insert(last_lsn);
+                    }
+                }
+                for (page_id, rec_lsn) in dirty_pages {
+                    let entry = dirty_page_table.entry(page_id).or_insert(rec_lsn);
+                    *entry = (*entry).min(rec_lsn);
+                }
+            }
+        }
+    }
+
+    // redo
+    let mut stats = RecoveryStats::default();
+    if let Some(&redo_lsn) = dirty_page_table.values().min() {
+        stats.redo_lsn = redo_lsn;
+        for record in log_manager.iter_from(redo_lsn) {
+            let record = record?;
+            let (page_id, offset, after) = match &record.body {
+                LogBody::Update {
+                    page_id,
+                    offset,
+                    after,
+                    ..
+                }
+                | LogBody::Compensation {
+                    page_id,
+                    offset,
+                    after,
+                    ..
+                } => (*page_id, *offset, after),
+                _ => continue,
+            };
+            if dirty_page_table
+                .get(&page_id)
+                .is_none_or(|&rec_lsn| record.lsn < rec_lsn)
+            {
+                continue;
+            }
+            let frame_id = fix(buffer_manager, page_id)?;
+            if get_page_lsn(buffer_manager.get_data_ref(frame_id)) < record.lsn {
+                buffer_manager.apply_update(frame_id, offset, after, record.lsn);
+                stats.num_redone += 1;
+            }
+            buffer_manager.unfix_page(page_id);
+        }
+    }
+
+    // undo
+    let mut losers: Vec<(TxnId, Lsn)> = txn_table.into_iter().collect();
+    losers.sort_unstable();
+    stats.num_undone = undo(buffer_manager, &losers)?;
+    stats.losers = losers.into_iter().map(|(txn_id, _)| txn_id).collect();
+    log_manager.flush_all()?;
+    Ok(stats)
+}
+
+/// Roll back each `(txn_id, last_lsn)` to its beginning, newest update
+/// first across all of them, and end each with an `End` record. Returns the
+/// number of updates undone.
+pub fn undo(buffer_manager: &mut BMgr, txns: &[(TxnId, Lsn)]) -> Result<usize, Error> {
+    let log_manager = buffer_manager
+        .get_log_manager()
+        .expect("no log manager")
+        .clone();
+    let mut last_lsns: HashMap<TxnId, Lsn> = txns.iter().copied().collect();
+    let mut to_undo: BinaryHeap<(Lsn, TxnId)> = txns
+        .iter()
+        .filter(|(_, lsn)| *lsn != INVALID_LSN)
+        .map(|&(txn_id, lsn)| (lsn, txn_id))
+        .collect();
+    let mut num_undone = 0;
+
+    while let Some((lsn, txn_id)) = to_undo.pop() {
+        let record = log_manager
+            .read_record(lsn)?
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "broken undo chain"))?;
+        let next_lsn = match record.body {
+            LogBody::Update {
+                page_id,
+                offset,
+                before,
+                ..
+            } => {
+                let last_lsn = last_lsns[&txn_id];
+                let clr_lsn = log_manager.append(
+                    last_lsn,
+                    txn_id,
+                    LogBody::Compensation {
+                        page_id,
+                        offset,
+                        after: before.clone(),
+                        undo_next_lsn: record.prev_lsn,
+                    },
+                );
+                last_lsns.insert(txn_id, clr_lsn);
+                let frame_id = fix(buffer_manager, page_id)?;
+                buffer_manager.apply_update(frame_id, offset, &before, clr_lsn);
+                buffer_manager.unfix_page(page_id);
+                num_undone += 1;
+                record.prev_lsn
+            }
+            // already undone before a crash
+            LogBody::Compensation { undo_next_lsn, .. } => undo_next_lsn,
+            _ => record.prev_lsn,
+        };
+        if next_lsn == INVALID_LSN {
+            log_manager.append(last_lsns[&txn_id], txn_id, LogBody::End);
+        } else {
+            to_undo.push((next_lsn, txn_id));
+        }
+    }
+    Ok(num_undone)
+}
+
+fn fix(buffer_manager: &mut BMgr, page_id: PageId) -> Result<FrameId, Error> {
+    buffer_manager
+        .fix_page(page_id, false)
+        .ok_or_else(|| Error::other("no free frame in buffer pool"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data_storage_manager::DSMgr;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::sync::Arc;
+
+    const CRASH_ENV: &str = "ADBS_CRASH_TEST_FILE";
+    const COMMITTED: &[u8] = b"committed";
+    const LOSER: &[u8] = b"loser";
+
+    fn open(file: &str, frame_num: usize) -> BMgr {
+        let log_manager = Arc::new(LogManager::open(&format!("{}.log", file)).unwrap());
+        let data_storage_manager = DSMgr::open(file, StorageOptions::default()).unwrap();
+        let mut buffer_manager =
+            BMgr::with_storage(data_storage_manager, ReplacePolicy::Lru, frame_num);
+        buffer_manager.set_log_manager(log_manager);
+        buffer_manager
+    }
+
+    fn update(buffer_manager: &mut BMgr, txn: (TxnId, &mut Lsn), page_id: PageId, offset: usize, bytes: &[u8]) {
+        let frame_id = buffer_manager.fix_page(page_id, false).unwrap();
+        *txn.1 = buffer_manager.log_update(txn.0, *txn.1, frame_id, offset, bytes);
+        buffer_manager.unfix_page(page_id);
+    }
+
+    fn read(buffer_manager: &mut BMgr, page_id: PageId, offset: usize, len: usize) -> Vec<u8> {
+        let frame_id = buffer_manager.fix_page(page_id, false).unwrap();
+        let bytes = buffer_manager.get_data_ref(frame_id)[offset..offset + len].to_vec();
+        buffer_manager.unfix_page(page_id);
+        bytes
+    }
+
+    /// Runs in a child process that is killed once the workload is done,
+    /// so neither the buffer pool nor the log buffer gets flushed on exit.
+    fn crash_workload(file: &str) {
+        let mut buffer_manager = open(file, 2);
+        let log_manager = buffer_manager.get_log_manager().unwrap().clone();
+        let mut page_id = 0;
+        for _ in 0..4 {
+            buffer_manager.fix_new_page(&mut page_id).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+
+        // txn 1 commits
+        let mut last_lsn = log_manager.append(INVALID_LSN, 1, LogBody::Begin);
+        update(&mut buffer_manager, (1, &mut last_lsn), 0, 100, COMMITTED);
+        update(&mut buffer_manager, (1, &mut last_lsn), 1, 100, COMMITTED);
+        let commit = log_manager.append(last_lsn, 1, LogBody::Commit);
+        log_manager.flush(commit).unwrap();
+
+        // txn 2 is still running at the crash; with two frames some of its
+        // updates are stolen to disk
+        let mut loser_lsn = log_manager.append(INVALID_LSN, 2, LogBody::Begin);
+        for page_id in [2, 3, 0] {
+            update(&mut buffer_manager, (2, &mut loser_lsn), page_id, 200, LOSER);
+        }
+
+        // txn 3 commits, but its page only reaches the log
+        let mut last_lsn = log_manager.append(INVALID_LSN, 3, LogBody::Begin);
+        update(&mut buffer_manager, (3, &mut last_lsn), 1, 300, COMMITTED);
+        let commit = log_manager.append(last_lsn, 3, LogBody::Commit);
+        log_manager.flush(commit).unwrap();
+
+        println!("workload done");
+        loop {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn crash_recovery_test() {
+        if let Ok(file) = std::env::var(CRASH_ENV) {
+            crash_workload(&file);
+        }
+
+        let file = format!("./target/test_file_{:?}_recovery.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let _ = std::fs::remove_file(format!("{}.log", file));
+
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "recovery::test::crash_recovery_test", "--nocapture"])
+            .env(CRASH_ENV, &file)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        assert!(stdout
+            .lines()
+            .any(|line| line.unwrap().contains("workload done")));
+        child.kill().unwrap();
+        assert!(!child.wait().unwrap().success());
+
+        {
+            let mut buffer_manager = open(&file, 2);
+            // the committed update of txn 3 never reached the data file
+            assert_eq!(read(&mut buffer_manager, 1, 300, COMMITTED.len()), vec![0; COMMITTED.len()]);
+        }
+        {
+            let mut buffer_manager = open(&file, 2);
+            let stats = recover(&mut buffer_manager).unwrap();
+            assert_eq!(stats.losers, vec![2]);
+            assert_eq!(stats.num_undone, 3);
+            assert!(stats.num_redone > 0);
+        }
+        // a second restart finds nothing to undo
+        let mut buffer_manager = open(&file, 2);
+        let stats = recover(&mut buffer_manager).unwrap();
+        assert!(stats.losers.is_empty());
+        assert_eq!(stats.num_redone, 0);
+
+        assert_eq!(read(&mut buffer_manager, 0, 100, COMMITTED.len()), COMMITTED);
+        assert_eq!(read(&mut buffer_manager, 1, 100, COMMITTED.len()), COMMITTED);
+        assert_eq!(read(&mut buffer_manager, 1, 300, COMMITTED.len()), COMMITTED);
+        for page_id in [0, 2, 3] {
+            assert_eq!(read(&mut buffer_manager, page_id, 200, LOSER.len()), vec![0; LOSER.len()]);
+        }
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(format!("{}.log", file));
+        let _ = std::fs::remove_file(file);
+    }
+
+    #[test]
+    fn checkpoint_recovery_test() {
+        let file = format!("./target/test_file_{:?}_checkpoint.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let _ = std::fs::remove_file(format!("{}.log", file));
+
+        let mut buffer_manager = open(&file, 8);
+        let log_manager = buffer_manager.get_log_manager().unwrap().clone();
+        let mut page_id = 0;
+        for _ in 0..4 {
+            buffer_manager.fix_new_page(&mut page_id).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        // the new pages are dirty without a recovery LSN; write them out
+        buffer_manager.checkpoint(&[], usize::MAX).unwrap();
+
+        let mut committed_lsn = log_manager.append(INVALID_LSN, 1, LogBody::Begin);
+        let mut loser_lsn = log_manager.append(INVALID_LSN, 2, LogBody::Begin);
+        update(&mut buffer_manager, (1, &mut committed_lsn), 0, 100, COMMITTED);
+        update(&mut buffer_manager, (2, &mut loser_lsn), 1, 200, LOSER);
+        update(&mut buffer_manager, (1, &mut committed_lsn), 2, 100, COMMITTED);
+        let write_io = buffer_manager.get_write_io_num();
+        let syncs = buffer_manager.get_sync_num();
+
+        // flush only the oldest dirty page, page 0, as one synced batch
+        let checkpoint = buffer_manager
+            .checkpoint(&[(1, committed_lsn), (2, loser_lsn)], 1)
+            .unwrap();
+        assert_eq!(buffer_manager.get_write_io_num(), write_io + 1);
+        assert_eq!(buffer_manager.get_sync_num(), syncs + 1);
+        assert_eq!(log_manager.get_master_lsn().unwrap(), checkpoint);
+        let dirty_pages: Vec<PageId> = {
+            let mut pages: Vec<PageId> = buffer_manager
+                .get_dirty_page_table()
+                .into_iter()
+                .map(|(page_id, _)| page_id)
+                .collect();
+            pages.sort_unstable();
+            pages
+        };
+        assert_eq!(dirty_pages, vec![1, 2]);
+
+        update(&mut buffer_manager, (2, &mut loser_lsn), 3, 200, LOSER);
+        let commit = log_manager.append(committed_lsn, 1, LogBody::Commit);
+        log_manager.flush(commit).unwrap();
+        // crash: neither the pool nor the rest of the log is flushed
+        std::mem::forget(buffer_manager);
+        drop(log_manager);
+
+        let mut buffer_manager = open(&file, 8);
+        let stats = recover(&mut buffer_manager).unwrap();
+        assert_eq!(stats.losers, vec![2]);
+        assert_eq!(stats.num_undone, 2);
+        // page 0 was flushed by the checkpoint, so redo starts after it
+        assert!(stats.redo_lsn > FIRST_LSN && stats.redo_lsn < checkpoint);
+        assert_eq!(read(&mut buffer_manager, 0, 100, COMMITTED.len()), COMMITTED);
+        assert_eq!(read(&mut buffer_manager, 2, 100, COMMITTED.len()), COMMITTED);
+        for page_id in [1, 3] {
+            assert_eq!(read(&mut buffer_manager, page_id, 200, LOSER.len()), vec![0; LOSER.len()]);
+        }
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(format!("{}.log", file));
+        let _ = std::fs::remove_file(file);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{config::FrameId, replacer::replacer::Replacer};
+use crate::{
+    config::{AccessType, FrameId},
+    replacer::replacer::Replacer,
+};
 use std::{collections::HashMap, ptr::NonNull};
 
 struct Node {
@@ -23,6 +26,7 @@ pub struct ClockReplacer {
     hand: Option<NonNull<Node>>, // current ptr
     map: HashMap<FrameId, NonNull<Node>>,
     capacity: usize,
+    scan_len: usize,
     marker: std::marker::PhantomData<Node>, // mark lifetime
 }
 
@@ -32,6 +36,7 @@ impl ClockReplacer {
             hand: None,
             map: HashMap::new(),
             capacity: frame_num,
+            scan_len: 0,
             marker: std::marker::PhantomData,
         }
     }
@@ -72,8 +77,10 @@ impl ClockReplacer {
 
 impl Replacer for ClockReplacer {
     fn victim(&mut self) -> Option<FrameId> {
+        self.scan_len = 0;
         loop {
             let mut hand = self.hand?;
+            self.scan_len += 1;
             unsafe {
                 if hand.as_ref().ref_ {
                     hand.as_mut().ref_ = false;
@@ -88,6 +95,27 @@ impl Replacer for ClockReplacer {
         }
     }
 
+    fn victim_if(&mut self, pred: &mut dyn FnMut(FrameId) -> bool) -> Option<FrameId> {
+        self.scan_len = 0;
+        // the first lap may only clear reference bits
+        for _ in 0..2 * self.size() {
+            let mut hand = self.hand?;
+            self.scan_len += 1;
+            unsafe {
+                let frame_id = hand.as_ref().frame_id;
+                if pred(frame_id) {
+                    if !hand.as_ref().ref_ {
+                        self.remove(frame_id);
+                        return Some(frame_id);
+                    }
+                    hand.as_mut().ref_ = false;
+                }
+                self.hand = hand.as_ref().next;
+            }
+        }
+        None
+    }
+
     fn insert(&mut self, frame_id: usize) {
         if let Some(node) = self.map.get(&frame_id) {
             // already in the replacer
@@ -155,7 +183,19 @@ impl Replacer for ClockReplacer {
             }
             self.detach(node);
             self.map.remove(&frame_id);
-            drop(node.as_ptr());
+            drop(unsafe { Box::from_raw(node.as_ptr()) });
+        }
+    }
+
+    fn insert_with_hint(&mut self, frame_id: usize, access_type: AccessType) {
+        self.insert(frame_id);
+        if access_type == AccessType::SequentialScan {
+            // clear the ref bit and put the hand on it
+            let mut node = self.map[&frame_id];
+            unsafe {
+                node.as_mut().ref_ = false;
+            }
+            self.hand = Some(node);
         }
     }
 
@@ -186,12 +226,24 @@ impl Replacer for ClockReplacer {
     fn size(&self) -> usize {
         self.map.len()
     }
+
+    fn get_scan_len(&self) -> usize {
+        self.scan_len
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        assert!(self.size() <= capacity);
+        self.capacity = capacity;
+    }
 }
 
+// the replacer owns its nodes exclusively, so it can move between threads
+unsafe impl Send for ClockReplacer {}
+
 impl Drop for ClockReplacer {
     fn drop(&mut self) {
         for (_, node) in self.map.iter() {
-            drop(node.as_ptr());
+            drop(unsafe { Box::from_raw(node.as_ptr()) });
         }
     }
 }
@@ -243,4 +295,34 @@ mod test {
         replacer.remove(3);
         assert_eq!(replacer.size(), 0);
     }
+
+    #[test]
+    fn clock_replacer_victim_if_test() {
+        let mut replacer = ClockReplacer::new(4);
+        replacer.insert(1);
+        replacer.insert(2);
+        replacer.insert(3);
+        // 1 is passed over with its reference bit, 2 and 3 lose theirs
+        assert_eq!(replacer.victim_if(&mut |frame_id| frame_id != 1), Some(2));
+        assert_eq!(replacer.get_scan_len(), 5);
+        assert_eq!(replacer.victim_if(&mut |_| false), None);
+        // 3 goes before 1, which still has its reference bit
+        assert_eq!(replacer.victim(), Some(3));
+        assert_eq!(replacer.size(), 1);
+    }
+
+    #[test]
+    fn clock_replacer_hint_test() {
+        let mut replacer = ClockReplacer::new(4);
+        replacer.insert(1);
+        replacer.insert(2);
+        replacer.insert_with_hint(3, AccessType::Lookup);
+        // a scanned frame is the next victim, without a sweep
+        replacer.insert_with_hint(4, AccessType::SequentialScan);
+        assert_eq!(replacer.victim(), Some(4));
+        assert_eq!(replacer.victim(), Some(1));
+        replacer.insert_with_hint(5, AccessType::SequentialScan);
+        assert_eq!(replacer.victim(), Some(5));
+        assert_eq!(replacer.size(), 2);
+    }
 }
@@ -1,6 +1,9 @@
 use std::{collections::HashMap, ptr::NonNull};
 
-use crate::{config::FrameId, replacer::replacer::Replacer};
+use crate::{
+    config::{AccessType, FrameId},
+    replacer::replacer::Replacer,
+};
 
 struct Node {
     frame_id: FrameId,
@@ -23,6 +26,7 @@ pub struct LruReplacer {
     tail: Option<NonNull<Node>>, // tail -> MRU
     map: HashMap<FrameId, NonNull<Node>>,
     capacity: usize,
+    scan_len: usize,
     marker: std::marker::PhantomData<Node>, // mark lifetime
 }
 
@@ -33,6 +37,7 @@ impl LruReplacer {
             tail: None,
             map: HashMap::new(),
             capacity: frame_num,
+            scan_len: 0,
             marker: std::marker::PhantomData,
         }
     }
@@ -81,18 +86,48 @@ impl LruReplacer {
             }
         }
     }
+
+    // attach at the LRU end
+    fn attach_head(&mut self, mut node: NonNull<Node>) {
+        match self.head {
+            Some(mut head) => unsafe {
+                head.as_mut().prev = Some(node);
+                node.as_mut().next = Some(head);
+                node.as_mut().prev = None;
+                self.head = Some(node);
+            },
+            None => self.attach(node),
+        }
+    }
 }
 
 impl Replacer for LruReplacer {
     fn victim(&mut self) -> Option<FrameId> {
+        self.scan_len = 0;
         let head = self.head?;
+        self.scan_len = 1;
         let victim_frame_id = unsafe { head.as_ref().frame_id };
         self.detach(head);
         self.map.remove(&victim_frame_id);
-        drop(head.as_ptr());
+        drop(unsafe { Box::from_raw(head.as_ptr()) });
         Some(victim_frame_id)
     }
 
+    fn victim_if(&mut self, pred: &mut dyn FnMut(FrameId) -> bool) -> Option<FrameId> {
+        self.scan_len = 0;
+        let mut node = self.head;
+        while let Some(current) = node {
+            self.scan_len += 1;
+            let frame_id = unsafe { current.as_ref().frame_id };
+            if pred(frame_id) {
+                self.remove(frame_id);
+                return Some(frame_id);
+            }
+            node = unsafe { current.as_ref().next };
+        }
+        None
+    }
+
     fn insert(&mut self, frame_id: usize) {
         // remove old node if exists
         if let Some(node) = self.map.get(&frame_id) {
@@ -110,12 +145,32 @@ impl Replacer for LruReplacer {
         }
     }
 
+    fn insert_with_hint(&mut self, frame_id: usize, access_type: AccessType) {
+        if access_type != AccessType::SequentialScan {
+            return self.insert(frame_id);
+        }
+        let node = match self.map.get(&frame_id) {
+            Some(node) => {
+                let node = *node;
+                self.detach(node);
+                node
+            }
+            None => {
+                let node = Box::into_raw(Box::new(Node::new(frame_id)));
+                let node = unsafe { NonNull::new_unchecked(node) };
+                self.map.insert(frame_id, node);
+                node
+            }
+        };
+        self.attach_head(node);
+    }
+
     fn remove(&mut self, frame_id: usize) {
         if let Some(node) = self.map.get(&frame_id) {
             let node = *node;
             self.detach(node);
             self.map.remove(&frame_id);
-            drop(node.as_ptr());
+            drop(unsafe { Box::from_raw(node.as_ptr()) });
         }
     }
 
@@ -134,14 +189,26 @@ impl Replacer for LruReplacer {
     fn size(&self) -> usize {
         self.map.len()
     }
+
+    fn get_scan_len(&self) -> usize {
+        self.scan_len
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        assert!(self.size() <= capacity);
+        self.capacity = capacity;
+    }
 }
 
+// the replacer owns its nodes exclusively, so it can move between threads
+unsafe impl Send for LruReplacer {}
+
 impl Drop for LruReplacer {
     fn drop(&mut self) {
         while let Some(node) = self.head.take() {
             unsafe {
                 self.head = node.as_ref().next;
-                drop(node.as_ptr());
+                drop(Box::from_raw(node.as_ptr()));
             }
         }
     }
@@ -186,4 +253,38 @@ mod test {
         }
         assert_eq!(replacer.size(), 5);
     }
+
+    #[test]
+    fn lru_replacer_victim_if_test() {
+        let mut replacer = LruReplacer::new(5);
+        replacer.insert(1);
+        replacer.insert(2);
+        replacer.insert(3);
+        replacer.insert_with_hint(4, AccessType::SequentialScan);
+        // 4 and 1 are passed over and stay at the LRU end
+        assert_eq!(replacer.victim_if(&mut |frame_id| frame_id > 1 && frame_id != 4), Some(2));
+        assert_eq!(replacer.get_scan_len(), 3);
+        assert_eq!(replacer.victim_if(&mut |_| false), None);
+        assert_eq!(replacer.victim(), Some(4));
+        assert_eq!(replacer.victim(), Some(1));
+        assert_eq!(replacer.size(), 1);
+    }
+
+    #[test]
+    fn lru_replacer_hint_test() {
+        let mut replacer = LruReplacer::new(5);
+        replacer.insert(1);
+        replacer.insert(2);
+        // scanned frames go to the LRU end, the latest first
+        replacer.insert_with_hint(3, AccessType::SequentialScan);
+        replacer.insert_with_hint(4, AccessType::SequentialScan);
+        replacer.insert_with_hint(5, AccessType::Index);
+        assert_eq!(replacer.victim().unwrap(), 4);
+        assert_eq!(replacer.victim().unwrap(), 3);
+        assert_eq!(replacer.victim().unwrap(), 1);
+        replacer.insert_with_hint(5, AccessType::SequentialScan);
+        assert_eq!(replacer.victim().unwrap(), 5);
+        assert_eq!(replacer.victim().unwrap(), 2);
+        assert_eq!(replacer.size(), 0);
+    }
 }
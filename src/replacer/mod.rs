@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 mod replacer;
 mod lru_replacer;
 mod clock_replacer;
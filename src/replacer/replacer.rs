@@ -1,9 +1,21 @@
-use crate::config::FrameId;
+use crate::config::{AccessType, FrameId};
 
 pub trait Replacer {
     fn victim(&mut self) -> Option<FrameId>;
+    /// Like `victim`, taking the first frame in eviction order for which
+    /// `pred` holds. The frames passed over keep their place and, for Clock,
+    /// their reference bit.
+    fn victim_if(&mut self, pred: &mut dyn FnMut(FrameId) -> bool) -> Option<FrameId>;
     fn insert(&mut self, frame_id: usize);
+    /// Insert a frame according to how its page was accessed: a page read
+    /// by a sequential scan becomes the next victim instead of the last.
+    fn insert_with_hint(&mut self, frame_id: usize, access_type: AccessType);
     fn remove(&mut self, frame_id: usize);
     fn print(&self);
     fn size(&self) -> usize;
+    /// The number of frames the last `victim` call looked at.
+    fn get_scan_len(&self) -> usize;
+    /// Change the number of frames the replacer tracks at most, when the
+    /// buffer pool is resized. Frames above it must have been removed.
+    fn set_capacity(&mut self, capacity: usize);
 }
@@ -0,0 +1,155 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::buffer_manager::BMgr;
+use crate::buffer_stats::BufferStats;
+use crate::config::*;
+use crate::data_storage_manager::DSMgr;
+
+/// A buffer pool shared between threads, split into shards by a hash of
+/// the `PageId`. Each shard is a `BMgr` with its own frames, page table and
+/// replacer behind its own lock, so threads touching different shards do
+/// not wait for each other. The price is that each shard evicts on its
+/// own: a hot shard cannot borrow frames from a cold one, which costs some
+/// hit rate compared with one `BMgr` of the same size.
+pub struct ShardedBMgr {
+    shards: Vec<Mutex<BMgr>>,
+}
+
+impl ShardedBMgr {
+    /// Split `frame_num` frames as evenly as possible over `shard_num` shards.
+    pub fn new(
+        data_storage_manager: DSMgr,
+        replace_policy: ReplacePolicy,
+        frame_num: usize,
+        shard_num: usize,
+    ) -> ShardedBMgr {
+        assert!(shard_num > 0 && frame_num >= shard_num);
+        let data_storage_manager = Arc::new(data_storage_manager);
+        let shards = (0..shard_num)
+            .map(|i| {
+                let shard_frame_num = frame_num / shard_num + usize::from(i < frame_num % shard_num);
+                Mutex::new(BMgr::with_shared_storage(
+                    data_storage_manager.clone(),
+                    replace_policy,
+                    shard_frame_num,
+                ))
+            })
+            .collect();
+        ShardedBMgr { shards }
+    }
+
+    pub fn get_shard_num(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Fibonacci hashing, so runs of adjacent page ids spread over the shards.
+    pub fn shard_of(&self, page_id: PageId) -> usize {
+        let hash = (page_id as u64).wrapping_mul(0x9e3779b97f4a7c15);
+        ((hash >> 32) % self.shards.len() as u64) as usize
+    }
+
+    /// Lock the shard caching `page_id`, to fix, access and unfix it in.
+    pub fn get_shard(&self, page_id: PageId) -> MutexGuard<'_, BMgr> {
+        self.shards[self.shard_of(page_id)].lock().unwrap()
+    }
+
+    /// Fix `page_id`, run `f` on its data and unfix it, all under the shard
+    /// lock. `None` if every frame of the shard is pinned.
+    pub fn with_page<R>(&self, page_id: PageId, is_dirty: bool, f: impl FnOnce(&mut Data) -> R) -> Option<R> {
+        let mut shard = self.get_shard(page_id);
+        let frame_id = shard.fix_page(page_id, is_dirty)?;
+        let result = f(shard.get_data(frame_id));
+        shard.unfix_page(page_id);
+        Some(result)
+    }
+
+    /// The stats of all shards added up.
+    pub fn get_stats(&self) -> BufferStats {
+        let mut shards = self.shards.iter().map(|shard| shard.lock().unwrap().get_stats());
+        let mut stats = shards.next().unwrap();
+        for shard_stats in shards {
+            stats.merge(&shard_stats);
+        }
+        stats
+    }
+
+    pub fn reset_stats(&self) {
+        for shard in &self.shards {
+            shard.lock().unwrap().reset_stats();
+        }
+    }
+
+    fn sum(&self, stat: impl Fn(&BMgr) -> usize) -> usize {
+        self.shards.iter().map(|shard| stat(&shard.lock().unwrap())).sum()
+    }
+
+    pub fn get_read_io_num(&self) -> usize {
+        self.sum(BMgr::get_read_io_num)
+    }
+
+    pub fn get_write_io_num(&self) -> usize {
+        self.sum(BMgr::get_write_io_num)
+    }
+
+    pub fn get_io_num(&self) -> usize {
+        self.sum(BMgr::get_io_num)
+    }
+
+    pub fn get_hit_num(&self) -> usize {
+        self.sum(BMgr::get_hit_num)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::page::PAGE_HEADER_SIZE;
+
+    #[test]
+    fn sharded_buffer_manager_test() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let data_storage_manager = DSMgr::new(&file);
+        data_storage_manager.new_pages(64);
+        let buffer_manager = ShardedBMgr::new(data_storage_manager, ReplacePolicy::Lru, 10, 4);
+        let frame_nums: Vec<usize> = buffer_manager
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().get_frame_num())
+            .collect();
+        assert_eq!(frame_nums, vec![3, 3, 2, 2]);
+        let shards: std::collections::HashSet<usize> = (0..8).map(|i| buffer_manager.shard_of(i)).collect();
+        assert_eq!(shards.len(), 4);
+
+        // every thread increments a counter on every page
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10 {
+                        for page_id in 0..64 {
+                            buffer_manager
+                                .with_page(page_id, true, |data| data[PAGE_HEADER_SIZE] += 1)
+                                .unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        for page_id in 0..64 {
+            let count = buffer_manager.with_page(page_id, false, |data| data[PAGE_HEADER_SIZE]);
+            assert_eq!(count, Some(40));
+        }
+        assert_eq!(
+            buffer_manager.get_hit_num() + buffer_manager.get_read_io_num(),
+            4 * 10 * 64 + 64
+        );
+        let stats = buffer_manager.get_stats();
+        assert_eq!(stats.hits + stats.misses, 4 * 10 * 64 + 64);
+        assert_eq!(stats.hit_latency.count, stats.hits);
+        buffer_manager.reset_stats();
+        assert_eq!(buffer_manager.get_hit_num(), 0);
+
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+}
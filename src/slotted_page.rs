@@ -0,0 +1,411 @@
+use std::fmt;
+
+use crate::config::*;
+use crate::page::{read_u16, read_u32, read_u64, write_u16, write_u32, PAGE_HEADER_SIZE};
+
+pub type SlotId = u16;
+
+/// The address of a record: a slot in a slotted page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordId {
+    pub page_id: PageId,
+    pub slot: SlotId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordError {
+    NotFound,
+    NoSpace,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordError::NotFound => write!(f, "record not found"),
+            RecordError::NoSpace => write!(f, "not enough free space in page"),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+// Layout (little endian), after the common page header:
+// | num_slots: u16 | unused: u16 | free_end: u32 | slot 0 | slot 1 | ... free ... | records |
+// A slot is (offset: u32, len: u32); records grow down from the end of the
+// page, the slot directory grows up after the header. Offset 0 marks an
+// empty slot, since no record can start inside the header.
+// The top bits of len are flags: a forwarding stub holds the id of the
+// record that moved out of its slot as (page_id: u64, slot: u16), and a
+// moved record is only reached through its stub.
+const NUM_SLOTS_OFFSET: usize = PAGE_HEADER_SIZE;
+const FREE_END_OFFSET: usize = PAGE_HEADER_SIZE + 4;
+const HEADER_SIZE: usize = PAGE_HEADER_SIZE + 8;
+const SLOT_SIZE: usize = 8;
+const FORWARD_FLAG: u32 = 1 << 31;
+const MOVED_FLAG: u32 = 1 << 30;
+const LEN_MASK: u32 = !(FORWARD_FLAG | MOVED_FLAG);
+const FORWARD_SIZE: usize = 10;
+
+/// A view of a page's data as a slotted page of variable-length records.
+pub struct SlottedPage<'a> {
+    data: &'a mut Data,
+}
+
+impl<'a> SlottedPage<'a> {
+    /// Format `data` as an empty slotted page.
+    pub fn init(data: &'a mut Data) -> SlottedPage<'a> {
+        let mut page = SlottedPage { data };
+        page.set_num_slots(0);
+        let page_size = page.data.len();
+        page.set_free_end(page_size);
+        page
+    }
+
+    /// View `data` that was formatted by `init` before.
+    pub fn new(data: &'a mut Data) -> SlottedPage<'a> {
+        SlottedPage { data }
+    }
+
+    /// The largest record an empty page of `page_size` bytes can hold.
+    pub fn max_record_size(page_size: usize) -> usize {
+        page_size - HEADER_SIZE - SLOT_SIZE
+    }
+
+    pub fn num_slots(&self) -> usize {
+        read_u16(self.data, NUM_SLOTS_OFFSET) as usize
+    }
+
+    pub fn num_records(&self) -> usize {
+        (0..self.num_slots()).filter(|&slot| self.slot(slot).0 != 0).count()
+    }
+
+    /// Bytes available to a new record, assuming it needs a new slot and the
+    /// page is compacted first.
+    pub fn free_space(&self) -> usize {
+        self.total_free().saturating_sub(SLOT_SIZE)
+    }
+
+    /// Insert a record moved here from the slot of a forwarding stub, see
+    /// `set_forward`.
+    pub fn insert_moved_record(&mut self, record: &[u8]) -> Option<SlotId> {
+        let slot = self.insert_record(record)?;
+        self.set_flags(slot as usize, MOVED_FLAG);
+        Some(slot)
+    }
+
+    pub fn insert_record(&mut self, record: &[u8]) -> Option<SlotId> {
+        let empty_slot = (0..self.num_slots()).find(|&slot| self.slot(slot).0 == 0);
+        let needed = record.len() + if empty_slot.is_some() { 0 } else { SLOT_SIZE };
+        if needed > self.total_free() || self.num_slots() == SlotId::MAX as usize {
+            return None;
+        }
+        if needed > self.contiguous_free() {
+            self.compact();
+        }
+
+        let slot = match empty_slot {
+            Some(slot) => slot,
+            None => {
+                let slot = self.num_slots();
+                self.set_num_slots(slot + 1);
+                slot
+            }
+        };
+        let offset = self.place(record);
+        self.set_slot(slot, offset, record.len());
+        Some(slot as SlotId)
+    }
+
+    /// The record in `slot`, moved or not. A forwarding stub holds none.
+    pub fn get_record(&self, slot: SlotId) -> Option<&[u8]> {
+        let (offset, len) = self.live_slot(slot)?;
+        if self.flags(slot as usize) & FORWARD_FLAG != 0 {
+            return None;
+        }
+        Some(&self.data[offset..offset + len])
+    }
+
+    /// Where the record of `slot` moved to, if `slot` holds a forwarding stub.
+    pub fn get_forward(&self, slot: SlotId) -> Option<RecordId> {
+        let (offset, _) = self.live_slot(slot)?;
+        if self.flags(slot as usize) & FORWARD_FLAG == 0 {
+            return None;
+        }
+        Some(RecordId {
+            page_id: read_u64(self.data, offset) as PageId,
+            slot: read_u16(self.data, offset + 8),
+        })
+    }
+
+    /// Whether `slot` holds a record moved here from a forwarding stub.
+    pub fn is_moved(&self, slot: SlotId) -> bool {
+        self.live_slot(slot).is_some() && self.flags(slot as usize) & MOVED_FLAG != 0
+    }
+
+    /// Whether the record in `slot` can be replaced by a forwarding stub.
+    pub fn can_forward(&self, slot: SlotId) -> bool {
+        match self.live_slot(slot) {
+            Some((_, len)) => FORWARD_SIZE <= self.total_free() + len,
+            None => false,
+        }
+    }
+
+    /// Replace the record or stub in `slot` with a forwarding stub to `target`.
+    pub fn set_forward(&mut self, slot: SlotId, target: RecordId) -> Result<(), RecordError> {
+        let mut stub = [0; FORWARD_SIZE];
+        stub[..8].copy_from_slice(&(target.page_id as u64).to_le_bytes());
+        stub[8..].copy_from_slice(&target.slot.to_le_bytes());
+        self.replace(slot, &stub)?;
+        self.set_flags(slot as usize, FORWARD_FLAG);
+        Ok(())
+    }
+
+    /// Overwrite the record in `slot`, which stays moved if it was.
+    pub fn update_record(&mut self, slot: SlotId, record: &[u8]) -> Result<(), RecordError> {
+        if self.get_record(slot).is_none() {
+            return Err(RecordError::NotFound);
+        }
+        let flags = self.flags(slot as usize);
+        self.replace(slot, record)?;
+        self.set_flags(slot as usize, flags);
+        Ok(())
+    }
+
+    fn replace(&mut self, slot: SlotId, record: &[u8]) -> Result<(), RecordError> {
+        let (offset, len) = self.live_slot(slot).ok_or(RecordError::NotFound)?;
+        if record.len() <= len {
+            self.data[offset..offset + record.len()].copy_from_slice(record);
+            self.set_slot(slot as usize, offset, record.len());
+            return Ok(());
+        }
+        if record.len() > self.total_free() + len {
+            return Err(RecordError::NoSpace);
+        }
+
+        // release the old copy, then place the record like an insert
+        self.set_slot(slot as usize, 0, 0);
+        if record.len() > self.contiguous_free() {
+            self.compact();
+        }
+        let offset = self.place(record);
+        self.set_slot(slot as usize, offset, record.len());
+        Ok(())
+    }
+
+    pub fn delete_record(&mut self, slot: SlotId) -> Result<(), RecordError> {
+        self.live_slot(slot).ok_or(RecordError::NotFound)?;
+        self.set_slot(slot as usize, 0, 0);
+        // trailing empty slots can be dropped from the directory
+        let mut num_slots = self.num_slots();
+        while num_slots > 0 && self.slot(num_slots - 1).0 == 0 {
+            num_slots -= 1;
+        }
+        self.set_num_slots(num_slots);
+        Ok(())
+    }
+
+    /// Move all records to the end of the page so that the free space
+    /// between the slot directory and the records is contiguous.
+    pub fn compact(&mut self) {
+        let mut slots: Vec<(usize, usize, usize)> = (0..self.num_slots())
+            .map(|slot| {
+                let (offset, len) = self.slot(slot);
+                (slot, offset, len)
+            })
+            .filter(|&(_, offset, _)| offset != 0)
+            .collect();
+        let flags: Vec<u32> = (0..self.num_slots()).map(|slot| self.flags(slot)).collect();
+        // packing towards the end never moves a record to a lower offset, so
+        // going from the highest offset down never overwrites a record not yet moved
+        slots.sort_unstable_by_key(|&(_, offset, _)| std::cmp::Reverse(offset));
+
+        let mut free_end = self.data.len();
+        for (slot, offset, len) in slots {
+            free_end -= len;
+            self.data.copy_within(offset..offset + len, free_end);
+            self.set_slot(slot, free_end, len);
+            self.set_flags(slot, flags[slot]);
+        }
+        self.set_free_end(free_end);
+    }
+
+    /// Iterate over the live records in slot order, moved ones included.
+    pub fn records(&self) -> impl Iterator<Item = (SlotId, &[u8])> + '_ {
+        (0..self.num_slots() as SlotId).filter_map(|slot| Some((slot, self.get_record(slot)?)))
+    }
+
+    fn place(&mut self, record: &[u8]) -> usize {
+        let offset = self.free_end() - record.len();
+        self.data[offset..offset + record.len()].copy_from_slice(record);
+        self.set_free_end(offset);
+        offset
+    }
+
+    fn contiguous_free(&self) -> usize {
+        self.free_end() - (HEADER_SIZE + self.num_slots() * SLOT_SIZE)
+    }
+
+    fn total_free(&self) -> usize {
+        let used: usize = (0..self.num_slots()).map(|slot| self.slot(slot).1).sum();
+        self.data.len() - HEADER_SIZE - self.num_slots() * SLOT_SIZE - used
+    }
+
+    fn live_slot(&self, slot: SlotId) -> Option<(usize, usize)> {
+        if slot as usize >= self.num_slots() {
+            return None;
+        }
+        let (offset, len) = self.slot(slot as usize);
+        if offset == 0 {
+            None
+        } else {
+            Some((offset, len))
+        }
+    }
+
+    fn slot(&self, slot: usize) -> (usize, usize) {
+        let pos = HEADER_SIZE + slot * SLOT_SIZE;
+        (read_u32(self.data, pos) as usize, (read_u32(self.data, pos + 4) & LEN_MASK) as usize)
+    }
+
+    fn flags(&self, slot: usize) -> u32 {
+        read_u32(self.data, HEADER_SIZE + slot * SLOT_SIZE + 4) & !LEN_MASK
+    }
+
+    fn set_flags(&mut self, slot: usize, flags: u32) {
+        let pos = HEADER_SIZE + slot * SLOT_SIZE + 4;
+        let len = read_u32(self.data, pos) & LEN_MASK;
+        write_u32(self.data, pos, len | flags);
+    }
+
+    /// Point `slot` at a record, clearing its flags.
+    fn set_slot(&mut self, slot: usize, offset: usize, len: usize) {
+        let pos = HEADER_SIZE + slot * SLOT_SIZE;
+        write_u32(self.data, pos, offset as u32);
+        write_u32(self.data, pos + 4, len as u32);
+    }
+
+    fn set_num_slots(&mut self, num_slots: usize) {
+        write_u16(self.data, NUM_SLOTS_OFFSET, num_slots as u16);
+    }
+
+    fn free_end(&self) -> usize {
+        read_u32(self.data, FREE_END_OFFSET) as usize
+    }
+
+    fn set_free_end(&mut self, free_end: usize) {
+        write_u32(self.data, FREE_END_OFFSET, free_end as u32);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer_manager::BMgr;
+
+    #[test]
+    fn slotted_page_test() {
+        let mut data = vec![0; PAGE_SIZE];
+        let mut page = SlottedPage::init(&mut data);
+        assert_eq!(page.num_records(), 0);
+
+        let a = page.insert_record(b"alpha").unwrap();
+        let b = page.insert_record(b"bravo bravo").unwrap();
+        let c = page.insert_record(b"").unwrap();
+        assert_eq!((a, b, c), (0, 1, 2));
+        assert_eq!(page.get_record(a).unwrap(), b"alpha");
+        assert_eq!(page.get_record(b).unwrap(), b"bravo bravo");
+        assert_eq!(page.get_record(c).unwrap(), b"");
+
+        page.update_record(a, b"al").unwrap();
+        assert_eq!(page.get_record(a).unwrap(), b"al");
+        page.update_record(a, b"alpha alpha alpha").unwrap();
+        assert_eq!(page.get_record(a).unwrap(), b"alpha alpha alpha");
+
+        page.delete_record(b).unwrap();
+        assert_eq!(page.get_record(b), None);
+        assert_eq!(page.delete_record(b), Err(RecordError::NotFound));
+        assert_eq!(page.update_record(b, b"x"), Err(RecordError::NotFound));
+        // the empty slot is reused
+        assert_eq!(page.insert_record(b"charlie").unwrap(), b);
+        assert_eq!(page.num_records(), 3);
+
+        let records: Vec<(SlotId, &[u8])> = page.records().collect();
+        assert_eq!(records, vec![(0, &b"alpha alpha alpha"[..]), (1, b"charlie"), (2, b"")]);
+
+        // a forwarding stub is no record, a moved record keeps its mark
+        let target = RecordId { page_id: 7, slot: 3 };
+        page.set_forward(b, target).unwrap();
+        assert_eq!((page.get_forward(b), page.get_record(b)), (Some(target), None));
+        assert_eq!(page.update_record(b, b"x"), Err(RecordError::NotFound));
+        let d = page.insert_moved_record(b"delta").unwrap();
+        page.update_record(d, b"delta delta").unwrap();
+        page.compact();
+        assert!(page.is_moved(d) && !page.is_moved(a));
+        assert_eq!(page.get_record(d).unwrap(), b"delta delta");
+        assert_eq!(page.get_forward(b), Some(target));
+        // an empty record on a full page has no room for a stub
+        assert!(page.can_forward(a));
+        page.delete_record(b).unwrap();
+        assert_eq!(page.get_forward(b), None);
+        assert_eq!(page.insert_record(b"echo").unwrap(), b);
+        assert!(!page.is_moved(b));
+    }
+
+    #[test]
+    fn slotted_page_compact_test() {
+        let mut data = vec![0; PAGE_SIZE];
+        let mut page = SlottedPage::init(&mut data);
+
+        // fill the page with 100-byte records
+        let mut slots = Vec::new();
+        while let Some(slot) = page.insert_record(&[slots.len() as u8; 100]) {
+            slots.push(slot);
+        }
+        assert!(page.free_space() < 100);
+        assert_eq!(page.update_record(slots[0], &[0; 200]), Err(RecordError::NoSpace));
+
+        // free every other record, then a large record only fits after compaction
+        for &slot in slots.iter().step_by(2) {
+            page.delete_record(slot).unwrap();
+        }
+        let big = vec![0xab; 1000];
+        let slot = page.insert_record(&big).unwrap();
+        assert_eq!(page.get_record(slot).unwrap(), &big[..]);
+        page.update_record(slots[1], &[0xcd; 700]).unwrap();
+        assert_eq!(page.get_record(slots[1]).unwrap(), &[0xcd; 700][..]);
+        for &slot in slots.iter().skip(3).step_by(2) {
+            assert_eq!(page.get_record(slot).unwrap(), &[slot as u8; 100][..]);
+        }
+
+        // survives re-opening the same data
+        let page = SlottedPage::new(&mut data);
+        assert_eq!(page.get_record(slot).unwrap(), &big[..]);
+    }
+
+    #[test]
+    fn slotted_page_buffer_manager_test() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let mut rids = Vec::new();
+        {
+            let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 2);
+            for i in 0..4 {
+                let mut page_id = 0;
+                let frame_id = buffer_manager.fix_new_page(&mut page_id).unwrap();
+                let mut page = SlottedPage::init(buffer_manager.get_data(frame_id));
+                let slot = page.insert_record(format!("record {}", i).as_bytes()).unwrap();
+                rids.push(RecordId { page_id, slot });
+                buffer_manager.unfix_page(page_id);
+            }
+        }
+
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Clock, 2);
+        for (i, rid) in rids.iter().enumerate() {
+            let frame_id = buffer_manager.fix_page(rid.page_id, false).unwrap();
+            let page = SlottedPage::new(buffer_manager.get_data(frame_id));
+            assert_eq!(page.get_record(rid.slot).unwrap(), format!("record {}", i).as_bytes());
+            buffer_manager.unfix_page(rid.page_id);
+        }
+        drop(buffer_manager);
+        let _ = std::fs::remove_file(file);
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::buffer_manager::BMgr;
+use crate::config::*;
+use crate::lock_manager::*;
+use crate::log_manager::*;
+use crate::recovery;
+
+#[derive(Debug)]
+pub enum TxnError {
+    /// The transaction was chosen as a deadlock victim.
+    Deadlock,
+    /// Every frame in the buffer pool is pinned.
+    BufferFull,
+    /// The transaction already committed or aborted.
+    NotActive,
+    /// The log or a page could not be written; the transaction stays active.
+    Io(std::io::Error),
+}
+
+impl fmt::Display for TxnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxnError::Deadlock => write!(f, "transaction aborted to break a deadlock"),
+            TxnError::BufferFull => write!(f, "no free frame in buffer pool"),
+            TxnError::NotActive => write!(f, "transaction is not active"),
+            TxnError::Io(err) => write!(f, "transaction I/O failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TxnError {}
+
+impl From<std::io::Error> for TxnError {
+    fn from(err: std::io::Error) -> Self {
+        TxnError::Io(err)
+    }
+}
+
+/// Transactions over byte ranges of buffer-pool pages. Reads and writes take
+/// shared and exclusive page locks held until commit or abort, writes are
+/// logged through `BMgr::log_update`, and an abort rolls the transaction back
+/// from the log with compensation records.
+///
+/// Threads share the pool as a `Mutex<BMgr>`. A read or write waits for its
+/// page lock before it locks the pool, so a transaction blocked on a lock
+/// never keeps the lock holder from reaching the pool to finish.
+pub struct TransactionManager {
+    log_manager: Arc<LogManager>,
+    lock_manager: LockManager,
+    next_txn_id: AtomicU64,
+    // active transactions with their last LSN
+    active_txns: Mutex<HashMap<TxnId, Lsn>>,
+}
+
+impl TransactionManager {
+    /// Transaction ids continue after the largest one in the log, so run
+    /// recovery first.
+    pub fn new(log_manager: Arc<LogManager>) -> TransactionManager {
+        let max_txn_id = log_manager
+            .iter_from(FIRST_LSN)
+            .map_while(Result::ok)
+            .map(|record| record.txn_id)
+            .max()
+            .unwrap_or(0);
+        TransactionManager {
+            log_manager,
+            lock_manager: LockManager::new(),
+            next_txn_id: AtomicU64::new(max_txn_id + 1),
+            active_txns: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get_lock_manager(&self) -> &LockManager {
+        &self.lock_manager
+    }
+
+    pub fn begin(&self) -> TxnId {
+        let txn_id = self.next_txn_id.fetch_add(1, Ordering::SeqCst);
+        let lsn = self.log_manager.append(INVALID_LSN, txn_id, LogBody::Begin);
+        self.active_txns.lock().unwrap().insert(txn_id, lsn);
+        txn_id
+    }
+
+    /// Lock `target` for `txn_id`, e.g. a record before reading it. A
+    /// deadlock victim must be aborted by the caller.
+    pub fn lock(&self, txn_id: TxnId, target: LockTarget, mode: LockMode) -> Result<(), TxnError> {
+        if !self.active_txns.lock().unwrap().contains_key(&txn_id) {
+            return Err(TxnError::NotActive);
+        }
+        self.lock_manager.lock(txn_id, target, mode)
+    }
+
+    pub fn read(
+        &self,
+        buffer_pool: &Mutex<BMgr>,
+        txn_id: TxnId,
+        page_id: PageId,
+        offset: usize,
+        len: usize,
+    ) -> Result<Vec<u8>, TxnError> {
+        self.lock(txn_id, LockTarget::Page(page_id), LockMode::Shared)?;
+        let mut buffer_manager = buffer_pool.lock().unwrap();
+        let frame_id = buffer_manager
+            .fix_page(page_id, false)
+            .ok_or(TxnError::BufferFull)?;
+        let bytes = buffer_manager.get_data_ref(frame_id)[offset..offset + len].to_vec();
+        buffer_manager.unfix_page(page_id);
+        Ok(bytes)
+    }
+
+    pub fn write(
+        &self,
+        buffer_pool: &Mutex<BMgr>,
+        txn_id: TxnId,
+        page_id: PageId,
+        offset: usize,
+        bytes: &[u8],
+    ) -> Result<(), TxnError> {
+        self.lock(txn_id, LockTarget::Page(page_id), LockMode::Exclusive)?;
+        let mut buffer_manager = buffer_pool.lock().unwrap();
+        let frame_id = buffer_manager
+            .fix_page(page_id, false)
+            .ok_or(TxnError::BufferFull)?;
+        let mut active_txns = self.active_txns.lock().unwrap();
+        let Some(last_lsn) = active_txns.get_mut(&txn_id) else {
+            buffer_manager.unfix_page(page_id);
+            return Err(TxnError::NotActive);
+        };
+        *last_lsn = buffer_manager.log_update(txn_id, *last_lsn, frame_id, offset, bytes);
+        buffer_manager.unfix_page(page_id);
+        Ok(())
+    }
+
+    /// Commit once the commit record is durable, then release the locks.
+    /// Under `ForcePolicy::Force` the transaction's pages are written first.
+    /// On an I/O error the transaction stays active and keeps its locks.
+    pub fn commit(&self, buffer_manager: &mut BMgr, txn_id: TxnId) -> Result<(), TxnError> {
+        let last_lsn = self.get_last_lsn(txn_id)?;
+        buffer_manager.commit_txn(txn_id)?;
+        let commit_lsn = self.log_manager.append(last_lsn, txn_id, LogBody::Commit);
+        self.log_manager.flush(commit_lsn)?;
+        self.active_txns.lock().unwrap().remove(&txn_id);
+        self.log_manager.append(commit_lsn, txn_id, LogBody::End);
+        self.lock_manager.unlock_all(txn_id);
+        Ok(())
+    }
+
+    /// Undo every update of the transaction, then release the locks. This
+    /// is also how a deadlock victim ends. On an I/O error the transaction
+    /// stays active and keeps its locks.
+    pub fn abort(&self, buffer_manager: &mut BMgr, txn_id: TxnId) -> Result<(), TxnError> {
+        let last_lsn = self.get_last_lsn(txn_id)?;
+        let abort_lsn = self.log_manager.append(last_lsn, txn_id, LogBody::Abort);
+        recovery::undo(buffer_manager, &[(txn_id, abort_lsn)])?;
+        self.active_txns.lock().unwrap().remove(&txn_id);
+        buffer_manager.abort_txn(txn_id);
+        self.lock_manager.unlock_all(txn_id);
+        Ok(())
+    }
+
+    fn get_last_lsn(&self, txn_id: TxnId) -> Result<Lsn, TxnError> {
+        self.active_txns.lock().unwrap().get(&txn_id).copied().ok_or(TxnError::NotActive)
+    }
+
+    /// The active transactions with their last LSN, for `BMgr::checkpoint`.
+    pub fn get_active_txns(&self) -> Vec<(TxnId, Lsn)> {
+        let active_txns = self.active_txns.lock().unwrap();
+        active_txns.iter().map(|(&txn_id, &lsn)| (txn_id, lsn)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn transaction_manager_test() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let log_file = format!("./target/test_file_{:?}.log", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let _ = std::fs::remove_file(&log_file);
+        let log_manager = Arc::new(LogManager::open(&log_file).unwrap());
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 4);
+        buffer_manager.set_log_manager(log_manager.clone());
+        let mut page_id = 0;
+        for _ in 0..2 {
+            buffer_manager.fix_new_page(&mut page_id).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        let buffer_pool = Mutex::new(buffer_manager);
+        let transaction_manager = TransactionManager::new(log_manager.clone());
+        let lock_manager = transaction_manager.get_lock_manager();
+
+        let txn = transaction_manager.begin();
+        transaction_manager.write(&buffer_pool, txn, 0, 100, b"first").unwrap();
+        transaction_manager.write(&buffer_pool, txn, 1, 100, b"first").unwrap();
+        assert_eq!(lock_manager.get_lock_mode(txn, LockTarget::Page(0)), Some(LockMode::Exclusive));
+        assert_eq!(transaction_manager.get_active_txns().len(), 1);
+        let commit_lsn = log_manager.get_next_lsn();
+        transaction_manager.commit(&mut buffer_pool.lock().unwrap(), txn).unwrap();
+        assert!(log_manager.get_flushed_lsn() > commit_lsn);
+        assert_eq!(lock_manager.get_lock_mode(txn, LockTarget::Page(0)), None);
+
+        // an ended transaction can neither write nor take locks again
+        assert!(matches!(
+            transaction_manager.write(&buffer_pool, txn, 0, 100, b"late"),
+            Err(TxnError::NotActive)
+        ));
+        assert_eq!(lock_manager.get_lock_mode(txn, LockTarget::Page(0)), None);
+        assert_eq!(buffer_pool.lock().unwrap().num_unpinned_frames(), 2);
+
+        // an aborted transaction leaves no trace and no locks
+        let txn = transaction_manager.begin();
+        transaction_manager.write(&buffer_pool, txn, 0, 100, b"second").unwrap();
+        transaction_manager.write(&buffer_pool, txn, 0, 200, b"second").unwrap();
+        assert_eq!(
+            transaction_manager.read(&buffer_pool, txn, 0, 100, 6).unwrap(),
+            b"second"
+        );
+        transaction_manager.abort(&mut buffer_pool.lock().unwrap(), txn).unwrap();
+        assert!(transaction_manager.get_active_txns().is_empty());
+
+        let reader = transaction_manager.begin();
+        assert!(reader > txn);
+        assert_eq!(
+            transaction_manager.read(&buffer_pool, reader, 0, 100, 6).unwrap(),
+            b"first\0"
+        );
+        assert_eq!(
+            transaction_manager.read(&buffer_pool, reader, 0, 200, 6).unwrap(),
+            vec![0; 6]
+        );
+        transaction_manager.commit(&mut buffer_pool.lock().unwrap(), reader).unwrap();
+
+        // a writer waiting for a page lock leaves the pool to the holder
+        let holder = transaction_manager.begin();
+        transaction_manager.write(&buffer_pool, holder, 1, 100, b"holder").unwrap();
+        std::thread::scope(|s| {
+            let waiter = s.spawn(|| {
+                let txn = transaction_manager.begin();
+                transaction_manager.write(&buffer_pool, txn, 1, 100, b"waiter").unwrap();
+                transaction_manager.commit(&mut buffer_pool.lock().unwrap(), txn).unwrap();
+            });
+            // let the waiter block on the page lock, then finish through the pool
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            transaction_manager.read(&buffer_pool, holder, 0, 100, 5).unwrap();
+            transaction_manager.commit(&mut buffer_pool.lock().unwrap(), holder).unwrap();
+        });
+        let reader = transaction_manager.begin();
+        assert_eq!(
+            transaction_manager.read(&buffer_pool, reader, 1, 100, 6).unwrap(),
+            b"waiter"
+        );
+        transaction_manager.commit(&mut buffer_pool.lock().unwrap(), reader).unwrap();
+
+        // transaction ids keep growing across restarts
+        drop(transaction_manager);
+        log_manager.flush_all().unwrap();
+        assert!(TransactionManager::new(log_manager).begin() > reader);
+
+        drop(buffer_pool);
+        let _ = std::fs::remove_file(file);
+        let _ = std::fs::remove_file(log_file);
+    }
+
+    #[test]
+    fn transaction_manager_deadlock_test() {
+        let file = format!("./target/test_file_{:?}.dbf", std::thread::current().id());
+        let log_file = format!("./target/test_file_{:?}.log", std::thread::current().id());
+        let _ = std::fs::remove_file(&file);
+        let _ = std::fs::remove_file(&log_file);
+        let log_manager = Arc::new(LogManager::open(&log_file).unwrap());
+        let mut buffer_manager = BMgr::new(&file, ReplacePolicy::Lru, 4);
+        buffer_manager.set_log_manager(log_manager.clone());
+        let mut page_id = 0;
+        for _ in 0..2 {
+            buffer_manager.fix_new_page(&mut page_id).unwrap();
+            buffer_manager.unfix_page(page_id);
+        }
+        let buffer_pool = Mutex::new(buffer_manager);
+        let transaction_manager = TransactionManager::new(log_manager.clone());
+
+        // each transaction writes its own page, then the other's
+        let barrier = std::sync::Barrier::new(2);
+        let mut results: Vec<Result<(), TxnError>> = std::thread::scope(|s| {
+            let handles: Vec<_> = [(0, 1), (1, 0)]
+                .into_iter()
+                .map(|(first, second)| {
+                    let (transaction_manager, buffer_pool, barrier) = (&transaction_manager, &buffer_pool, &barrier);
+                    s.spawn(move || {
+                        let txn = transaction_manager.begin();
+                        transaction_manager.write(buffer_pool, txn, first, 100, b"mine").unwrap();
+                        barrier.wait();
+                        match transaction_manager.write(buffer_pool, txn, second, 200, b"theirs") {
+                            Ok(()) => transaction_manager.commit(&mut buffer_pool.lock().unwrap(), txn),
+                            Err(err) => {
+                                // the victim's locks are only released by the abort
+                                transaction_manager.abort(&mut buffer_pool.lock().unwrap(), txn).unwrap();
+                                assert!(matches!(
+                                    transaction_manager.abort(&mut buffer_pool.lock().unwrap(), txn),
+                                    Err(TxnError::NotActive)
+                                ));
+                                Err(err)
+                            }
+                        }
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        results.sort_by_key(Result::is_ok);
+        assert!(matches!(results[..], [Err(TxnError::Deadlock), Ok(())]));
+        assert_eq!(transaction_manager.get_lock_manager().get_deadlock_num(), 1);
+        assert!(transaction_manager.get_active_txns().is_empty());
+
+        drop(buffer_pool);
+        let _ = std::fs::remove_file(file);
+        let _ = std::fs::remove_file(log_file);
+    }
+}